@@ -0,0 +1,41 @@
+//! Renders a file as a minimap in an HTML page, with a span for each run of a color.
+//!
+//!     cargo run --example html -- src/lib.rs > minimap.html
+
+use megamap::ansi;
+use megamap::prelude::*;
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "src/lib.rs".to_string());
+
+    let printer = PrinterBuilder::new()
+        .color_mode(ColorMode::TrueColor)
+        .build();
+    let mut output = Vec::new();
+    printer.print_source(&mut output, &InputSource::File(path.into()))?;
+
+    println!("<!DOCTYPE html>");
+    println!("<pre style=\"background: #272822; line-height: 1\">");
+    // the printer colors the cells with escape sequences, which are turned into spans
+    let mut current = None;
+    for line in String::from_utf8_lossy(&output).lines() {
+        for (text, color) in ansi::parse(line, &mut current) {
+            let text = text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            match color {
+                Some(color) => print!(
+                    "<span style=\"color: #{:02x}{:02x}{:02x}\">{}</span>",
+                    color.r, color.g, color.b, text
+                ),
+                None => print!("{}", text),
+            }
+        }
+        println!();
+    }
+    println!("</pre>");
+    Ok(())
+}
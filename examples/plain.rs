@@ -0,0 +1,19 @@
+//! Renders a file as a minimap into a string, without colors.
+//!
+//!     cargo run --example plain -- src/lib.rs
+
+use megamap::capture::PlainCapture;
+use megamap::prelude::*;
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "src/lib.rs".to_string());
+
+    let printer = PrinterBuilder::new().columns(40).build();
+    let mut capture = PlainCapture::new();
+    printer.print_source(&mut capture, &InputSource::File(path.into()))?;
+
+    print!("{}", capture.into_string());
+    Ok(())
+}
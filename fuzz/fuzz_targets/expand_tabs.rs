@@ -0,0 +1,17 @@
+//! Expands tabs in arbitrary text with arbitrary tab stops.
+//!
+//!     cargo +nightly fuzz run expand_tabs
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use megamap::printer::fuzzing;
+use unicode_width::UnicodeWidthStr;
+
+fuzz_target!(|input: (&str, Vec<u8>, u8)| {
+    let (line, stops, tabs) = input;
+    let stops: Vec<usize> = stops.into_iter().map(usize::from).collect();
+    let expanded = fuzzing::expand_tabs(line, &stops, tabs.into());
+    assert!(!expanded.contains('\t'));
+    assert!(expanded.width() >= line.width());
+});
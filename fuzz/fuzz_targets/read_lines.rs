@@ -0,0 +1,18 @@
+//! Reads the lines of arbitrary input.
+//!
+//!     cargo +nightly fuzz run read_lines
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use megamap::printer::fuzzing;
+
+fuzz_target!(|data: &[u8]| {
+    // invalid UTF-8 is an error rather than a panic
+    if let Ok(lines) = fuzzing::read_lines(data) {
+        let newlines = data.iter().filter(|byte| **byte == b'\n').count();
+        let unterminated = !data.is_empty() && !data.ends_with(b"\n");
+        assert_eq!(lines.len(), newlines + unterminated as usize);
+        assert!(lines.iter().all(|line| !line.contains('\n')));
+    }
+});
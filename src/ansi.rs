@@ -0,0 +1,91 @@
+use syntect::highlighting::Color;
+
+/// Splits `line` into runs of text colored by the SGR sequences in it, dropping all
+/// escape sequences.
+///
+/// `color` is the foreground color in effect, `None` for the default one. It is carried
+/// over from the previous line and updated, as colors may span lines.
+pub fn parse(line: &str, color: &mut Option<Color>) -> Vec<(String, Option<Color>)> {
+    let mut runs = Vec::new();
+    let mut text = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            text.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // control sequence, ending with a byte in @ to ~
+            Some('[') => {
+                let mut params = String::new();
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        if c == 'm' {
+                            let new_color = apply_sgr(&params, *color);
+                            if new_color != *color && !text.is_empty() {
+                                runs.push((std::mem::take(&mut text), *color));
+                            }
+                            *color = new_color;
+                        }
+                        break;
+                    }
+                    params.push(c);
+                }
+            }
+            // operating system command, ending with BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' {
+                        chars.next_if_eq(&'\\');
+                        break;
+                    }
+                }
+            }
+            // other sequences take a single character
+            _ => {}
+        }
+    }
+    if !text.is_empty() || runs.is_empty() {
+        runs.push((text, *color));
+    }
+    runs
+}
+
+/// Returns the foreground color after the SGR sequence with `params` (e.g. "1;38;5;208").
+fn apply_sgr(params: &str, mut color: Option<Color>) -> Option<Color> {
+    let mut params = params.split([';', ':']).map(|param| {
+        // an empty parameter means 0
+        param.parse::<u8>().unwrap_or(0)
+    });
+    while let Some(param) = params.next() {
+        match param {
+            0 | 39 => color = None,
+            30..=37 => color = Some(ansi256(param - 30)),
+            90..=97 => color = Some(ansi256(param - 90 + 8)),
+            38 | 48 => {
+                let extended = match params.next() {
+                    Some(5) => params.next().map(ansi256),
+                    Some(2) => match (params.next(), params.next(), params.next()) {
+                        (Some(r), Some(g), Some(b)) => Some(Color { r, g, b, a: 0xff }),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if param == 38 && extended.is_some() {
+                    color = extended;
+                }
+            }
+            _ => {}
+        }
+    }
+    color
+}
+
+fn ansi256(index: u8) -> Color {
+    let (r, g, b) = ansi_colours::rgb_from_ansi256(index);
+    Color { r, g, b, a: 0xff }
+}
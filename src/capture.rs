@@ -0,0 +1,57 @@
+use std::io::{self, Write};
+
+/// A writer keeping only the text of what is written to it, with escape sequences
+/// for colors and attributes stripped.
+///
+/// Useful for checking the layout of a minimap regardless of how colors are encoded.
+#[derive(Default)]
+pub struct PlainCapture {
+    text: Vec<u8>,
+    state: State,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Text,
+    // after ESC
+    Escape,
+    // inside a control sequence (ESC [ ...)
+    ControlSequence,
+}
+
+impl PlainCapture {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the captured text, with invalid UTF-8 replaced.
+    pub fn into_string(self) -> String {
+        String::from_utf8_lossy(&self.text).into_owned()
+    }
+}
+
+impl Write for PlainCapture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.state = match (self.state, byte) {
+                (State::Text, 0x1b) => State::Escape,
+                (State::Text, _) => {
+                    self.text.push(byte);
+                    State::Text
+                }
+                (State::Escape, b'[') => State::ControlSequence,
+                // other escape sequences are two bytes long
+                (State::Escape, _) => State::Text,
+                // parameter and intermediate bytes
+                (State::ControlSequence, 0x20..=0x3f) => State::ControlSequence,
+                (State::ControlSequence, _) => State::Text,
+            };
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! Line-level diffs for marking the changes of an input against another text.
+
+// differences beyond this many edits are not looked into, bounding the memory taken
+// by the trace of the search to the square of it
+const MAX_EDITS: usize = 1024;
+
+/// How a line of the new text differs from the old text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineChange {
+    /// The line is not in the old text
+    Added,
+    /// The line replaces lines of the old text
+    Changed,
+    /// Lines of the old text were removed right above the line
+    RemovedAbove,
+    /// Lines of the old text were removed right below the line, which is the last one
+    RemovedBelow,
+}
+
+pub struct LineDiff {
+    // whether each line of the new text is not in the old one
+    added: Vec<bool>,
+    // number of lines of the old text removed right before each line of the new one,
+    // and after the last one
+    removed_before: Vec<usize>,
+}
+
+impl LineDiff {
+    /// Compares the lines of two texts.
+    pub fn new<T: PartialEq>(old: &[T], new: &[T]) -> Self {
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_middle = &old[prefix..old.len() - suffix];
+        let new_middle = &new[prefix..new.len() - suffix];
+
+        let mut diff = Self {
+            added: vec![false; new.len()],
+            removed_before: vec![0; new.len() + 1],
+        };
+        match edit_script(old_middle, new_middle) {
+            Some(edits) => {
+                for edit in edits {
+                    match edit {
+                        Edit::Insert(line) => diff.added[prefix + line] = true,
+                        Edit::Delete(line) => diff.removed_before[prefix + line] += 1,
+                    }
+                }
+            }
+            // too different to tell which lines correspond
+            None => {
+                diff.added[prefix..new.len() - suffix].fill(true);
+                diff.removed_before[prefix] = old_middle.len();
+            }
+        }
+        diff
+    }
+
+    /// Returns how line `line` (0-based) of the new text differs, if it does.
+    pub fn change(&self, line: usize) -> Option<LineChange> {
+        let added = *self.added.get(line)?;
+        let removed_above = self.removed_before[line] > 0;
+        if added && removed_above {
+            Some(LineChange::Changed)
+        } else if added {
+            Some(LineChange::Added)
+        } else if removed_above {
+            Some(LineChange::RemovedAbove)
+        } else if line + 1 == self.added.len() && self.removed_before[line + 1] > 0 {
+            Some(LineChange::RemovedBelow)
+        } else {
+            None
+        }
+    }
+}
+
+enum Edit {
+    // line of the new text inserted
+    Insert(usize),
+    // a line of the old text deleted before the line of the new text
+    Delete(usize),
+}
+
+/// Finds the shortest edit script from `a` to `b` with the Myers algorithm, or `None`
+/// if it takes more than `MAX_EDITS` edits.
+fn edit_script<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDITS) as isize;
+    // furthest x on each diagonal k = x - y, indexed by k + offset
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // v around the diagonals reachable before each number of edits
+    let mut trace = Vec::new();
+
+    let mut found = false;
+    'search: for d in 0..=max {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_y as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    Some(edits)
+}
@@ -0,0 +1,131 @@
+use lazy_static::lazy_static;
+use syntect::highlighting::{
+    HighlightState, Highlighter, RangedHighlightIterator, ScopeSelectors, Style, Theme,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+lazy_static! {
+    // scopes where brackets do not nest
+    static ref NON_CODE: ScopeSelectors = "string, comment".parse().unwrap();
+}
+
+/// A highlighted piece of a line.
+pub struct Region<'a> {
+    pub style: Style,
+    pub text: &'a str,
+    /// Scope stack in effect for the whole region
+    pub scopes: ScopeStack,
+}
+
+/// State of highlighting before a line, for highlighting a fragment of a text (e.g. the
+/// lines an editor shows) as if the lines before it had been highlighted.
+///
+/// The parser state (`syntect::parsing::ParseState`) is the stack of contexts the
+/// parser is in, such as a block comment, and belongs to the syntax it was made with.
+/// The scope stack (`syntect::parsing::ScopeStack`) holds the scopes in effect, which
+/// select the colors of the theme. Nesting of brackets is not kept.
+#[derive(Clone, Debug)]
+pub struct HighlightStart {
+    pub parse_state: ParseState,
+    pub scopes: ScopeStack,
+}
+
+impl HighlightStart {
+    /// State at the start of a text in `syntax`.
+    pub fn new(syntax: &SyntaxReference) -> Self {
+        Self {
+            parse_state: ParseState::new(syntax),
+            scopes: ScopeStack::new(),
+        }
+    }
+
+    /// Advances the state past `line`, which includes its newline if any.
+    pub fn advance(&mut self, line: &str, syntax_set: &SyntaxSet) {
+        for (_, op) in self.parse_state.parse_line(line, syntax_set) {
+            self.scopes.apply(&op);
+        }
+    }
+}
+
+/// Highlights lines one by one like `syntect::easy::HighlightLines`,
+/// but also reports the scope stack of each region.
+pub struct LineHighlighter<'a> {
+    highlighter: Highlighter<'a>,
+    parse_state: ParseState,
+    highlight_state: HighlightState,
+    plain_text: bool,
+    bracket_depth: usize,
+}
+
+impl<'a> LineHighlighter<'a> {
+    pub fn new(syntax: &SyntaxReference, theme: &'a Theme) -> Self {
+        Self::with_start(syntax, theme, HighlightStart::new(syntax))
+    }
+
+    /// Highlights lines following the ones that `start` was advanced past.
+    pub fn with_start(syntax: &SyntaxReference, theme: &'a Theme, start: HighlightStart) -> Self {
+        let highlighter = Highlighter::new(theme);
+        let highlight_state = HighlightState::new(&highlighter, start.scopes);
+        Self {
+            highlighter,
+            parse_state: start.parse_state,
+            highlight_state,
+            plain_text: syntax.name == "Plain Text",
+            bracket_depth: 0,
+        }
+    }
+
+    /// Whether the syntax is syntect's fallback syntax for plain text.
+    pub fn is_plain_text(&self) -> bool {
+        self.plain_text
+    }
+
+    /// Returns the nesting depth of brackets at each char of `region`, continuing from
+    /// the regions passed before. Brackets in strings and comments are ignored.
+    pub fn bracket_depths(&mut self, region: &Region) -> Vec<usize> {
+        let code = NON_CODE.does_match(region.scopes.as_slice()).is_none();
+        region
+            .text
+            .chars()
+            .map(|c| {
+                if code && matches!(c, ')' | ']' | '}') {
+                    self.bracket_depth = self.bracket_depth.saturating_sub(1);
+                }
+                let depth = self.bracket_depth;
+                if code && matches!(c, '(' | '[' | '{') {
+                    self.bracket_depth += 1;
+                }
+                depth
+            })
+            .collect()
+    }
+
+    pub fn highlight<'b>(&mut self, line: &'b str, syntax_set: &SyntaxSet) -> Vec<Region<'b>> {
+        let ops = self.parse_state.parse_line(line, syntax_set);
+
+        // RangedHighlightIterator splits the line at every op and skips empty pieces,
+        // so replaying the ops on the stack yields the scopes of each region in order.
+        let mut stack = self.highlight_state.path.clone();
+        let mut stacks = Vec::new();
+        let mut pos = 0;
+        for (end, op) in &ops {
+            if *end > pos {
+                stacks.push(stack.clone());
+                pos = *end;
+            }
+            stack.apply(op);
+        }
+        if line.len() > pos {
+            stacks.push(stack);
+        }
+
+        RangedHighlightIterator::new(&mut self.highlight_state, &ops, line, &self.highlighter)
+            .zip(stacks)
+            .map(|((style, text, _), scopes)| Region {
+                style,
+                text,
+                scopes,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Escape sequence protocol for displaying images inline in a terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageProtocol {
+    Iterm2,
+    Kitty,
+}
+
+impl ImageProtocol {
+    /// Guesses the protocol supported by the terminal from the environment.
+    pub fn detect() -> Option<Self> {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        if std::env::var_os("KITTY_WINDOW_ID").is_some() || var("TERM") == "xterm-kitty" {
+            Some(Self::Kitty)
+        } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm") {
+            Some(Self::Iterm2)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for ImageProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "iterm2" => Ok(Self::Iterm2),
+            "kitty" => Ok(Self::Kitty),
+            _ => Err(anyhow!("expected iterm2 or kitty")),
+        }
+    }
+}
+
+/// RGBA pixels, transparent unless painted.
+pub struct Raster {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Raster {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    /// Paints a rectangle, clipped to the raster.
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, rgba: [u8; 4]) {
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                let i = (y * self.width + x) * 4;
+                self.pixels[i..i + 4].copy_from_slice(&rgba);
+            }
+        }
+    }
+
+    /// Encodes the raster as a PNG. The image data is stored without compression,
+    /// which is fine for the small images of minimaps.
+    fn to_png(&self) -> Vec<u8> {
+        let mut scanlines = Vec::with_capacity((self.width * 4 + 1) * self.height);
+        for row in self.pixels.chunks(self.width * 4).take(self.height) {
+            // filter type: none
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut header = Vec::new();
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // bit depth 8, truecolor with alpha, default compression, filter and interlace
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+/// Writes `raster` with the escape sequences of `protocol`, followed by a newline.
+pub fn write_image<W: Write>(
+    writer: &mut W,
+    raster: &Raster,
+    protocol: ImageProtocol,
+) -> io::Result<()> {
+    let png = raster.to_png();
+    let data = base64(&png);
+    match protocol {
+        ImageProtocol::Iterm2 => write!(
+            writer,
+            "\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07",
+            png.len(),
+            data
+        )?,
+        ImageProtocol::Kitty => {
+            // the payload has to be sent in chunks of at most 4096 bytes
+            let chunks: Vec<_> = data.as_bytes().chunks(4096).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = (i + 1 < chunks.len()) as u8;
+                if i == 0 {
+                    write!(writer, "\x1b_Gf=100,a=T,m={};", more)?;
+                } else {
+                    write!(writer, "\x1b_Gm={};", more)?;
+                }
+                writer.write_all(chunk)?;
+                write!(writer, "\x1b\\")?;
+            }
+        }
+    }
+    writeln!(writer)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
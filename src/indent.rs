@@ -0,0 +1,87 @@
+//! Detection of how text is indented.
+
+use std::collections::BTreeMap;
+
+/// Whitespace that lines are indented with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentStyle {
+    Tabs,
+    /// Spaces, in levels of the given width
+    Spaces(usize),
+}
+
+/// How the lines of a text are indented.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndentReport {
+    /// Style of most indented lines, or `None` if no line is indented
+    pub style: Option<IndentStyle>,
+    /// Number of indented lines that are not blank
+    pub indented_lines: usize,
+    /// Number of indented lines not following `style`, i.e. indented with spaces in a
+    /// text indented with tabs, or with tabs or a partial level in one indented with
+    /// spaces
+    pub deviating_lines: usize,
+}
+
+/// Detects the indentation of `lines`, ignoring blank ones.
+pub fn detect_indent<'a, I>(lines: I) -> IndentReport
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let indents: Vec<&str> = lines
+        .into_iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let content = line.trim_start_matches([' ', '\t']);
+            &line[..line.len() - content.len()]
+        })
+        .collect();
+
+    let indented = indents.iter().filter(|indent| !indent.is_empty());
+    let tabs = indented
+        .clone()
+        .filter(|indent| !indent.contains(' '))
+        .count();
+    let spaces = indented
+        .clone()
+        .filter(|indent| !indent.contains('\t'))
+        .count();
+    let indented_lines = indented.clone().count();
+    let style = if indented_lines == 0 {
+        None
+    } else if tabs > spaces {
+        Some(IndentStyle::Tabs)
+    } else {
+        Some(IndentStyle::Spaces(space_width(&indents)))
+    };
+
+    let deviating_lines = indented
+        .filter(|indent| match style {
+            Some(IndentStyle::Tabs) => indent.contains(' '),
+            Some(IndentStyle::Spaces(width)) => indent.contains('\t') || indent.len() % width != 0,
+            None => false,
+        })
+        .count();
+    IndentReport {
+        style,
+        indented_lines,
+        deviating_lines,
+    }
+}
+
+/// Guesses the width of a level of indentation with spaces as the most common increase
+/// of it from a line to the next, preferring narrower widths.
+fn space_width(indents: &[&str]) -> usize {
+    let mut increases: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut previous = 0;
+    for indent in indents.iter().filter(|indent| !indent.contains('\t')) {
+        if indent.len() > previous {
+            *increases.entry(indent.len() - previous).or_default() += 1;
+        }
+        previous = indent.len();
+    }
+    increases
+        .into_iter()
+        .max_by(|(a_width, a), (b_width, b)| a.cmp(b).then(b_width.cmp(a_width)))
+        .map_or(1, |(width, _)| width)
+}
@@ -0,0 +1,79 @@
+#[cfg(feature = "git")]
+use anyhow::anyhow;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Where the text of an input comes from.
+pub enum InputSource {
+    /// Standard input, after the bytes already read ahead from it
+    Stdin(Vec<u8>),
+    File(PathBuf),
+    /// File at a git revision as rev:path, read with `git show`
+    #[cfg(feature = "git")]
+    Rev(String),
+    /// Text built into the executable, with a file name to detect the language from
+    Static {
+        name: &'static str,
+        text: &'static str,
+    },
+}
+
+impl InputSource {
+    /// Standard input for "-", or the file at `path` otherwise.
+    pub fn from_path(path: PathBuf) -> Self {
+        if path.as_os_str() == "-" {
+            Self::Stdin(Vec::new())
+        } else {
+            Self::File(path)
+        }
+    }
+
+    /// File name to detect the language from, if any.
+    pub fn path_hint(&self) -> Option<&Path> {
+        match self {
+            Self::Stdin(_) => None,
+            Self::File(path) => Some(path),
+            #[cfg(feature = "git")]
+            Self::Rev(rev) => rev.split_once(':').map(|(_, path)| Path::new(path)),
+            Self::Static { name, .. } => Some(Path::new(name)),
+        }
+    }
+
+    /// Opens the input for reading. Files stay open only as long as the reader, which
+    /// `Printer` drops once an input is printed, so one file is open at a time.
+    pub fn open(&self) -> Result<Box<dyn BufRead>> {
+        match self {
+            Self::Stdin(read_ahead) => Ok(Box::new(
+                io::Cursor::new(read_ahead.clone()).chain(io::stdin().lock()),
+            )),
+            Self::File(path) => Ok(Box::new(BufReader::new(File::open(path)?))),
+            #[cfg(feature = "git")]
+            Self::Rev(rev) => {
+                if !rev.contains(':') {
+                    return Err(anyhow!("expected rev:path but got {}", rev));
+                }
+                Ok(Box::new(io::Cursor::new(git_show(rev)?)))
+            }
+            Self::Static { text, .. } => Ok(Box::new(text.as_bytes())),
+        }
+    }
+}
+
+/// Reads a blob with `git show`.
+#[cfg(feature = "git")]
+fn git_show(object: &str) -> Result<Vec<u8>> {
+    let output = std::process::Command::new("git")
+        .args(["show", object])
+        .output()
+        .map_err(|err| anyhow!("failed to run git: {}", err))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git show {} failed: {}",
+            object,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,27 @@
+//! Renders minimaps of source code in the terminal, as the `megamap` command does.
+//!
+//! Configure a [`Printer`] with a [`PrinterBuilder`] and print inputs to any writer.
+//! The [`prelude`] re-exports what is needed for that, and the programs in the `examples`
+//! directory show how it fits together. Errors are [`anyhow`] errors.
+
+pub mod ansi;
+pub mod capture;
+pub mod diff;
+pub mod highlight;
+#[cfg(feature = "image")]
+pub mod image;
+pub mod indent;
+pub mod input;
+#[cfg(feature = "notebook")]
+pub mod notebook;
+pub mod prelude;
+pub mod printer;
+pub mod theme;
+
+pub use anyhow::{Error, Result};
+pub use highlight::HighlightStart;
+pub use input::InputSource;
+pub use printer::{
+    detect_syntax, highlight_start_after, ColorMode, Dimensions, Printer, PrinterBuilder,
+    RenderConfig,
+};
@@ -1,10 +1,28 @@
-mod printer;
+mod tui;
 
-use anyhow::Result;
-use printer::PrinterBuilder;
-use std::io;
-use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+use crossterm::tty::IsTty;
+#[cfg(feature = "image")]
+use megamap::image;
+use megamap::indent::{self, IndentStyle};
+use megamap::input::InputSource;
+use megamap::printer::{
+    self, ColorMode, ColumnUnit, Dimensions, PadWidth, Printer, PrinterBuilder, RowTerminator,
+    ScopeFilter, Timings,
+};
+use megamap::theme;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use structopt::StructOpt;
+use syntect::highlighting::{Color, ScopeSelectors};
+use tui::AlternateScreen;
+use unicode_width::UnicodeWidthChar;
 
 #[derive(StructOpt)]
 #[structopt(
@@ -18,64 +36,1740 @@ struct Opt {
     /// Use a "-" or no argument for standard input.
     file: Vec<PathBuf>,
 
+    /// Render a file at a git revision (e.g. HEAD~3:src/main.rs)
+    ///
+    /// Can be specified multiple times. Rendered after the files given as arguments.
+    #[cfg(feature = "git")]
+    #[structopt(long, value_name = "rev:path", number_of_values = 1)]
+    rev: Vec<String>,
+
     /// Explicitly set the language for syntax highlighting
     ///
     /// Languages can be specified as a name (e.g. rust) or an extension (e.g. rs).
+    /// A comma-separated list (e.g. tsx,ts) is tried in order until one is found.
+    /// Jupyter notebooks are then rendered as they are instead of by cell.
     #[structopt(short, long)]
     language: Option<String>,
 
-    /// Maximum number of columns
+    /// Set the language of standard input, overriding --language for it
+    ///
+    /// Useful in pipelines where the format of the input is known but cannot be detected.
+    #[structopt(long, value_name = "language")]
+    stdin_language: Option<String>,
+
+    /// Detect files with an extension as a language (e.g. tsx=jsx)
+    ///
+    /// Can be specified multiple times.
+    #[structopt(
+        long,
+        value_name = "ext=lang",
+        number_of_values = 1,
+        parse(try_from_str = parse_map_ext)
+    )]
+    map_ext: Vec<(String, String)>,
+
+    /// Theme for syntax highlighting
+    ///
+    /// Either the name of a built-in theme or the path to a .tmTheme file.
+    /// Use a "-" to read the theme from standard input, which requires giving files.
+    #[structopt(long, value_name = "name|path", default_value = theme::DEFAULT_THEME)]
+    theme: String,
+
+    /// Fail instead of falling back to another theme if the theme cannot be loaded
+    ///
+    /// Guarantees reproducible output, e.g. in CI.
+    #[structopt(long)]
+    require_theme: bool,
+
+    /// Load all .tmTheme files in a directory as themes named after the files
+    ///
+    /// They can be selected with --theme and are listed with the built-in themes.
+    #[structopt(long, value_name = "path")]
+    theme_dir: Option<PathBuf>,
+
+    /// List the available themes
+    #[structopt(long)]
+    list_themes: bool,
+
+    /// List the available themes with sample colors in JSON
+    #[structopt(long)]
+    theme_list_json: bool,
+
+    /// Render a built-in code sample instead of files to preview the theme
+    #[structopt(long, conflicts_with_all = &["file", "rev"])]
+    theme_preview: bool,
+
+    /// Maximum number of columns, or auto for the terminal width
+    ///
+    /// With auto, lines are not truncated unless writing to a terminal.
     #[structopt(short, long)]
-    columns: Option<usize>,
+    columns: Option<Columns>,
+
+    /// What --columns counts: cells, or chars for characters regardless of their widths
+    ///
+    /// With chars, lines of wide characters (e.g. CJK) take more cells than --columns.
+    #[structopt(
+        long,
+        value_name = "unit",
+        default_value = "cells",
+        parse(try_from_str = parse_column_unit)
+    )]
+    column_unit: ColumnUnit,
+
+    /// Maximum number of columns as a percentage of the terminal width
+    #[structopt(long, value_name = "percent", conflicts_with = "columns")]
+    columns_percent: Option<usize>,
 
     /// Tab width
     ///
     /// Specify 0 to pass tabs through.
     #[structopt(short, long)]
     tabs: Option<usize>,
+
+    /// Comma-separated columns tabs advance to (e.g. 4,12,20)
+    ///
+    /// Past the last stop, tabs advance by the interval between the last two stops.
+    #[structopt(
+        long,
+        value_name = "columns",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    tab_stops: Vec<usize>,
+
+    /// Pass tabs through instead of expanding them to spaces
+    ///
+    /// Tabs still advance to the next tab stop when counting columns.
+    #[structopt(long)]
+    no_expand_tabs: bool,
+
+    /// Print the filename and the first line of each file as a title
+    ///
+    /// Useful for files starting with a shebang or a doc comment.
+    #[structopt(long)]
+    first_line_as_title: bool,
+
+    /// Print the dimensions of each rendered minimap to stderr
+    ///
+    /// Dimensions are printed as "rows=<n> cols=<n>" after each file.
+    #[structopt(long)]
+    print_dims: bool,
+
+    /// When to use colors: auto, always, or never
+    ///
+    /// With auto, colors are disabled if $NO_COLOR is set. Otherwise they are forced if
+    /// $CLICOLOR_FORCE is set to anything but 0, disabled if $CLICOLOR is 0 or, except on
+    /// Windows, if $TERM is dumb or unset, and enabled only when writing to a terminal in
+    /// any other case.
+    #[structopt(long, value_name = "when", default_value = "auto")]
+    color: ColorChoice,
+
+    /// Use the terminal's 16-color palette
+    ///
+    /// Colors are mapped to the nearest of the basic 16 colors, so the minimap follows
+    /// the terminal's color scheme.
+    #[structopt(long)]
+    ansi16: bool,
+
+    /// Override the color of a scope (e.g. string=#ff0000)
+    ///
+    /// Can be specified multiple times. The most specific matching scope wins.
+    #[structopt(long, value_name = "scope=color", number_of_values = 1, parse(try_from_str = parse_recolor))]
+    recolor: Vec<(ScopeSelectors, Color)>,
+
+    /// Emphasize tokens matching a scope (e.g. entity.name.function) by dimming the others
+    ///
+    /// Can be specified multiple times.
+    #[structopt(long, value_name = "scope", number_of_values = 1, parse(try_from_str = parse_scope))]
+    emphasize_scope: Vec<ScopeSelectors>,
+
+    /// Color of plain text (e.g. #c0c0c0)
+    ///
+    /// Defaults to the theme's foreground color. Fixing it makes the output of plain text
+    /// independent of the theme.
+    #[structopt(long, value_name = "color", parse(try_from_str = parse_color))]
+    plain_color: Option<Color>,
+
+    /// Multiply colors by the given factor
+    #[structopt(long, default_value = "1.0")]
+    brightness: f32,
+
+    /// Scale the contrast of colors by the given factor
+    #[structopt(long, default_value = "1.0")]
+    contrast: f32,
+
+    /// Render only the first L and the last R columns of long lines (e.g. 40:20)
+    #[structopt(
+        long,
+        value_name = "L:R",
+        parse(try_from_str = parse_edges),
+        conflicts_with_all = &["columns", "columns-percent"]
+    )]
+    edges: Option<(usize, usize)>,
+
+    /// Fit the minimap of each file in W columns and H rows (e.g. 40x30)
+    ///
+    /// Columns and lines are skipped evenly as needed.
+    #[structopt(
+        long,
+        value_name = "WxH",
+        parse(try_from_str = parse_fit),
+        conflicts_with_all = &["columns", "columns-percent", "scale", "edges", "wrap", "follow", "split-on"]
+    )]
+    fit: Option<(usize, usize)>,
+
+    /// Render only the first N lines of each file
+    ///
+    /// The rest of a file is not read.
+    #[structopt(long, value_name = "N", conflicts_with_all = &["tail", "fit", "follow"])]
+    head: Option<usize>,
+
+    /// Render only the last N lines of each file
+    ///
+    /// Lines before them are not highlighted, so constructs starting earlier (e.g. block
+    /// comments) may be highlighted as code. Line numbers given to other options count
+    /// from the first line rendered.
+    #[structopt(long, value_name = "N", conflicts_with = "fit")]
+    tail: Option<usize>,
+
+    /// Collapse every N source columns into a single column
+    #[structopt(long, value_name = "N", default_value = "1")]
+    scale: usize,
+
+    /// Draw every N source lines in a row
+    ///
+    /// Two lines are drawn in the halves of cells, and more lines in full cells of
+    /// their average colors.
+    #[structopt(
+        long,
+        value_name = "N",
+        conflicts_with_all = &[
+            "edges", "heatmap", "wrap", "ink-profile", "rtl", "rotate", "guide", "ramp",
+            "eighth-blocks", "gradient", "mark-longest", "highlight-line", "annotate",
+            "blank-marker", "with-source", "diff", "fit"
+        ]
+    )]
+    vscale: Option<usize>,
+
+    /// Glyphs ordered from empty to full (e.g. " .:-=+*#%@")
+    ///
+    /// Each cell is drawn with the glyph matching how much of its source columns are not
+    /// whitespace, which is most useful with --scale. The first glyph is used for whitespace.
+    #[structopt(long, value_name = "glyphs")]
+    ramp: Option<String>,
+
+    /// Draw cells as left eighth blocks filled up to where their source columns end
+    ///
+    /// With --scale, this shows where lines end within a cell, giving smoother edges.
+    /// Cells of single source columns are drawn as full blocks.
+    #[structopt(long, conflicts_with = "ramp")]
+    eighth_blocks: bool,
+
+    /// Draw control characters other than tabs as the given glyph in magenta
+    ///
+    /// Otherwise they take no space, which hides stray control bytes.
+    #[structopt(long, value_name = "glyph")]
+    control_glyph: Option<char>,
+
+    /// Mark the K longest lines of each file in a gutter
+    ///
+    /// Each input is read to the end before it is rendered.
+    #[structopt(long, value_name = "K", conflicts_with = "follow")]
+    mark_longest: Option<usize>,
+
+    /// Emphasize the line with the given number by dimming the others
+    ///
+    /// Can be specified multiple times.
+    #[structopt(short = "H", long, value_name = "line", number_of_values = 1)]
+    highlight_line: Vec<usize>,
+
+    /// Mark lines listed in a file in a gutter
+    ///
+    /// Each line of the file is line:color[:label] (e.g. 42:#ff0000:TODO).
+    /// Empty lines and lines starting with # are ignored.
+    #[structopt(long, value_name = "file")]
+    annotate: Option<PathBuf>,
+
+    /// Mark where the file differs from another file in a gutter
+    ///
+    /// Added lines are tinted green and changed lines yellow, and red marks show where
+    /// lines were removed.
+    #[structopt(long, value_name = "other", conflicts_with_all = &["gradient", "follow", "rotate"])]
+    diff: Option<PathBuf>,
+
+    /// Draw faint vertical lines at every indentation level (multiples of the tab width)
+    #[structopt(long)]
+    indent_guides: bool,
+
+    /// Collapse runs of whitespace between tokens into single blanks
+    ///
+    /// Indentation is kept as is.
+    #[structopt(long)]
+    compact_whitespace: bool,
+
+    /// Draw a faint mark on blank lines to make them countable
+    #[structopt(long)]
+    blank_marker: bool,
+
+    /// Render lines with fewer than N non-whitespace source columns as blank
+    ///
+    /// Useful for decluttering lines with only a closing brace.
+    #[structopt(long, value_name = "N", default_value = "0")]
+    min_ink: usize,
+
+    /// Draw a guide at the given column to spot lines exceeding it
+    #[structopt(long, value_name = "column", conflicts_with = "edges")]
+    guide: Option<usize>,
+
+    /// Flag lines exceeding --guide in a gutter
+    #[structopt(long, requires = "guide")]
+    guide_ticks: bool,
+
+    /// Color code by the nesting depth of brackets instead of the theme
+    ///
+    /// Brackets in strings and comments are ignored.
+    #[structopt(long)]
+    rainbow_depth: bool,
+
+    /// Comma-separated colors cycled through by --rainbow-depth (e.g. #ff0000,#00ff00)
+    #[structopt(
+        long,
+        value_name = "colors",
+        use_delimiter = true,
+        require_delimiter = true,
+        requires = "rainbow-depth",
+        parse(try_from_str = parse_color)
+    )]
+    depth_palette: Vec<Color>,
+
+    /// Fill the background with a vertical gradient between two colors (e.g. #101020,#203040)
+    ///
+    /// Each input is read to the end before it is rendered.
+    #[structopt(long, value_name = "top,bottom", parse(try_from_str = parse_gradient))]
+    gradient: Option<(Color, Color)>,
+
+    /// Fill the background of whitespace with a color (e.g. #272822)
+    ///
+    /// Useful when the background of the terminal differs from the theme's.
+    #[structopt(long, value_name = "color", parse(try_from_str = parse_color))]
+    whitespace_bg: Option<Color>,
+
+    /// Warn if the theme is hard to see on the terminal's background, and fill whitespace
+    /// with the theme's background if so
+    ///
+    /// The terminal's background is read from $COLORFGBG. Nothing is checked without it.
+    #[structopt(long, conflicts_with_all = &["whitespace-bg", "deterministic"])]
+    theme_auto_contrast: bool,
+
+    /// Pad every row with whitespace to N columns, or auto for the widest row of each file
+    ///
+    /// Makes minimaps rectangular, with --whitespace-bg filling the padding.
+    #[structopt(
+        long,
+        value_name = "N|auto",
+        parse(try_from_str = parse_pad_to),
+        conflicts_with_all = &["rotate", "vscale", "ink-profile", "image"]
+    )]
+    pad_to: Option<PadWidth>,
+
+    /// Render lines longer than this many bytes as plain text
+    ///
+    /// Highlighting huge lines such as minified code is slow. Specify 0 for no limit.
+    #[structopt(long, value_name = "bytes", default_value = "65536")]
+    max_line_length: usize,
+
+    /// Color text with the ANSI escape sequences in the input instead of highlighting it
+    ///
+    /// Useful for captured program output. Escape sequences are never rendered as text.
+    #[structopt(long)]
+    ansi_passthrough: bool,
+
+    /// Print control characters from inputs in titles and source text as they are
+    ///
+    /// They are shown as symbols (e.g. ␛ for escape) by default, as escape sequences in
+    /// inputs could reconfigure the terminal.
+    #[structopt(long)]
+    raw_control_chars: bool,
+
+    /// Render lines that fail to be highlighted as plain text instead of aborting
+    #[structopt(long)]
+    no_highlight_errors: bool,
+
+    /// Collapse each line into a single cell of its average color
+    ///
+    /// This makes a one column wide overview of the color of each line.
+    #[structopt(long, conflicts_with_all = &["edges", "guide"])]
+    heatmap: bool,
+
+    /// Wrap long lines onto the following rows instead of truncating them
+    ///
+    /// Continuation rows are indented like the line they continue.
+    #[structopt(long, conflicts_with_all = &["edges", "heatmap"])]
+    wrap: bool,
+
+    /// Render only comments
+    #[structopt(long, conflicts_with = "only-code")]
+    only_comments: bool,
+
+    /// Render everything but comments
+    #[structopt(long)]
+    only_code: bool,
+
+    /// What ends each row: lf, crlf, or none
+    #[structopt(long, value_name = "terminator", default_value = "lf", parse(try_from_str = parse_row_terminator))]
+    row_terminator: RowTerminator,
+
+    /// Print a single row showing how many lines have ink at each column instead
+    ///
+    /// Brighter cells mean more lines are not whitespace at the column, which reveals
+    /// common indentation levels and alignment.
+    #[structopt(long, conflicts_with_all = &["edges", "heatmap", "wrap", "follow"])]
+    ink_profile: bool,
+
+    /// Print the most used colors of each file with their shares after its minimap
+    ///
+    /// Shows 8 colors unless a count is given as --palette-summary=<count>.
+    #[structopt(long, value_name = "count", require_equals = true)]
+    palette_summary: Option<Option<usize>>,
+
+    /// Print the color of each major kind of scope in the language given with --language
+    /// instead of minimaps
+    ///
+    /// Serves as a color reference for the combination of the language and the theme.
+    #[structopt(
+        long,
+        requires = "language",
+        conflicts_with_all = &["file", "rev", "theme-preview", "count-only", "validate"]
+    )]
+    legend_only: bool,
+
+    /// Split standard input into separate files at lines starting with the delimiter
+    ///
+    /// Each part is rendered under its delimiter line, with the language detected from
+    /// a file name in it (e.g. --split-on "diff --git" for the output of git show).
+    #[structopt(long, value_name = "delimiter")]
+    split_on: Option<String>,
+
+    /// Flush the output after every row
+    ///
+    /// This is the default when writing to a terminal.
+    #[structopt(long)]
+    flush_lines: bool,
+
+    /// Keep rendering lines appended to the file, like tail -f
+    ///
+    /// Useful for watching logs. Press Ctrl-C to stop.
+    #[cfg(feature = "follow")]
+    #[structopt(short, long, conflicts_with_all = &["tui", "concat", "gradient"])]
+    follow: bool,
+
+    /// Render the minimap as an inline image with the protocol iterm2 or kitty
+    ///
+    /// Without a value, the protocol is detected from the terminal.
+    #[cfg(feature = "image")]
+    #[structopt(
+        long,
+        value_name = "protocol",
+        require_equals = true,
+        conflicts_with_all = &["edges", "heatmap", "wrap", "follow", "ink-profile", "rotate", "vscale"]
+    )]
+    image: Option<Option<image::ImageProtocol>>,
+
+    /// Print the number of files and lines of each detected language instead of minimaps
+    ///
+    /// Inputs that are unreadable or not UTF-8 text are skipped with a warning.
+    #[structopt(long)]
+    count_only: bool,
+
+    /// Check that each input can be rendered instead of printing minimaps
+    ///
+    /// Prints the status of each input and fails if any is unreadable or not UTF-8 text.
+    /// Inputs of a language that cannot be detected are reported without failing, as they
+    /// are rendered as plain text.
+    #[structopt(long, conflicts_with = "count-only")]
+    validate: bool,
+
+    /// Report how each file is indented instead of printing minimaps
+    ///
+    /// Prints whether most indented lines use tabs or spaces, with the width of a level
+    /// of spaces, and fails if any lines deviate from that. Inputs that are unreadable or
+    /// not UTF-8 text are reported as errors and fail too.
+    #[structopt(long, conflicts_with_all = &["count-only", "validate", "legend-only"])]
+    detect_indent_and_report: bool,
+
+    /// Render right to left, with gutters on the right
+    ///
+    /// Each input is read to the end before it is rendered.
+    #[structopt(
+        long,
+        conflicts_with_all = &["edges", "heatmap", "wrap", "guide", "follow"]
+    )]
+    rtl: bool,
+
+    /// Print the highlighted source to the left of the minimap
+    ///
+    /// The source takes 80 columns unless a width is given as --with-source=<width>.
+    #[structopt(
+        long,
+        value_name = "width",
+        require_equals = true,
+        conflicts_with_all = &[
+            "edges", "heatmap", "wrap", "ink-profile", "rtl", "rotate", "blank-marker",
+            "no-expand-tabs"
+        ]
+    )]
+    with_source: Option<Option<usize>>,
+
+    /// Render lines as columns from left to right, making a horizontal strip
+    ///
+    /// Each row shows two source columns, and --columns limits the number of them.
+    /// Each input is kept in memory until its last line, so large files take a lot of it.
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "edges", "heatmap", "wrap", "follow", "ink-profile", "rtl", "guide", "ramp",
+            "eighth-blocks", "gradient", "mark-longest", "highlight-line", "annotate",
+            "blank-marker"
+        ]
+    )]
+    rotate: bool,
+
+    /// Tint each file with a different hue to tell files apart
+    #[structopt(long)]
+    tint_by_file: bool,
+
+    /// Render only files modified within a duration (e.g. 7d)
+    ///
+    /// Durations are numbers followed by s, m, h, d, or w.
+    #[structopt(long, value_name = "duration", parse(try_from_str = parse_duration))]
+    since: Option<Duration>,
+
+    /// Render all files as one continuous minimap
+    ///
+    /// Files are separated by thin rules unless --separator is given.
+    #[structopt(long, conflicts_with = "first-line-as-title")]
+    concat: bool,
+
+    /// What to print between files: none, line, or blank
+    ///
+    /// Defaults to line with --concat and none otherwise.
+    #[structopt(long, value_name = "style")]
+    separator: Option<Separator>,
+
+    /// Group files by language, printing the name of each language above its files
+    ///
+    /// Languages are sorted by name, and files keep their order within each language.
+    #[structopt(
+        long,
+        conflicts_with_all = &["follow", "output-dir", "count-only", "validate", "legend-only"]
+    )]
+    group_by_extension: bool,
+
+    /// Write the minimap of each file to a file in a directory instead of standard output
+    ///
+    /// Files are named after the inputs with the extension .ans, or .txt without colors.
+    /// Use --color always to color them when standard output is not a terminal.
+    #[structopt(
+        long,
+        value_name = "path",
+        conflicts_with_all = &["concat", "separator", "tui", "follow", "image", "count-only", "validate", "legend-only"]
+    )]
+    output_dir: Option<PathBuf>,
+
+    /// Make the output independent of the environment, e.g. for snapshot tests
+    ///
+    /// Colors are printed in 24-bit unless --color=never or --ansi16 is given, lines are
+    /// truncated at 80 columns unless --columns is given, and the terminal and environment
+    /// variables are not queried. The theme must load as given.
+    #[structopt(long, conflicts_with_all = &["columns-percent", "tui"])]
+    deterministic: bool,
+
+    /// Print the time spent in each phase to stderr after rendering
+    #[structopt(long)]
+    profile: bool,
+
+    /// Do not print warnings or progress
+    ///
+    /// Errors are still reported through the exit status. Progress through several
+    /// inputs is shown when stderr is a terminal that the minimaps do not go to.
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Display the minimap in the alternate screen
+    ///
+    /// Press q to quit and return to the original screen.
+    #[structopt(long)]
+    tui: bool,
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
+    QUIET.store(opt.quiet, Ordering::Relaxed);
+
+    if let Some(dir) = &opt.theme_dir {
+        for err in theme::load_theme_dir(dir)? {
+            warn(err);
+        }
+    }
+
+    if opt.list_themes {
+        let mut stdout = io::stdout();
+        for name in theme::theme_names() {
+            writeln!(stdout, "{}", name)?;
+        }
+        return Ok(());
+    }
+    if opt.theme_list_json {
+        let json = serde_json::to_string_pretty(&theme::theme_list_json())?;
+        writeln!(io::stdout(), "{}", json)?;
+        return Ok(());
+    }
+
+    let colored = if opt.deterministic {
+        opt.color != ColorChoice::Never
+    } else {
+        opt.color.is_enabled()
+    };
+    let color_mode = if !colored {
+        ColorMode::NoColor
+    } else if opt.ansi16 {
+        ColorMode::Ansi16
+    } else if opt.deterministic {
+        ColorMode::TrueColor
+    } else {
+        ColorMode::detect()
+    };
+
+    let started = Instant::now();
+    printer::load_syntaxes();
+    let mut profile = vec![("syntaxes", started.elapsed())];
+
+    if opt.theme == "-" {
+        #[cfg(feature = "git")]
+        let revs_given = !opt.rev.is_empty();
+        #[cfg(not(feature = "git"))]
+        let revs_given = false;
+        let reads_stdin = opt.file.iter().any(|file| file.as_os_str() == "-")
+            || (opt.file.is_empty() && !revs_given && !opt.theme_preview && !opt.legend_only);
+        if reads_stdin {
+            return Err(anyhow!(
+                "the theme is read from standard input with --theme -, so files have to be given"
+            ));
+        }
+    }
+
+    let started = Instant::now();
+    let theme = theme::resolve_theme(&opt.theme, opt.require_theme || opt.deterministic)?;
+    profile.push(("theme", started.elapsed()));
+
+    let mut whitespace_bg = opt.whitespace_bg;
+    if opt.theme_auto_contrast {
+        if let (Some(terminal), Some(foreground)) =
+            (terminal_background(), theme.settings.foreground)
+        {
+            let ratio = theme::contrast_ratio(foreground, terminal);
+            if ratio < MIN_CONTRAST {
+                match theme.settings.background {
+                    Some(background) => {
+                        warn(format!(
+                            "the theme has a low contrast of {:.1}:1 against the terminal's background, filling whitespace with the theme's background",
+                            ratio
+                        ));
+                        whitespace_bg = Some(background);
+                    }
+                    None => warn(format!(
+                        "the theme has a low contrast of {:.1}:1 against the terminal's background",
+                        ratio
+                    )),
+                }
+            }
+        }
+    }
 
     let mut builder = PrinterBuilder::new();
-    builder.true_color(true_color_is_enabled());
-    if let Some(lang) = opt.language {
-        builder.language(&lang);
+    builder
+        .theme(theme)
+        .color_mode(color_mode)
+        .expand_tabs(!opt.no_expand_tabs)
+        .brightness(opt.brightness)
+        .contrast(opt.contrast)
+        .max_line_length(opt.max_line_length)
+        .heatmap(opt.heatmap)
+        .wrap(opt.wrap)
+        .row_terminator(opt.row_terminator)
+        .ink_profile(opt.ink_profile)
+        .indent_guides(opt.indent_guides)
+        .compact_whitespace(opt.compact_whitespace)
+        .blank_marker(opt.blank_marker)
+        .min_ink(opt.min_ink)
+        .highlight_fallback(opt.no_highlight_errors)
+        .ansi_passthrough(opt.ansi_passthrough)
+        .column_unit(opt.column_unit)
+        .raw_control_chars(opt.raw_control_chars)
+        .first_line_as_title(opt.first_line_as_title)
+        .profile(opt.profile)
+        .flush_lines(opt.flush_lines || (!opt.deterministic && io::stdout().is_tty()));
+    for (selectors, color) in opt.recolor {
+        builder.recolor(selectors, color);
+    }
+    for selectors in opt.emphasize_scope {
+        builder.emphasize_scope(selectors);
+    }
+    if let Some(color) = opt.plain_color {
+        builder.plain_color(color);
+    }
+    if let Some(color) = whitespace_bg {
+        builder.whitespace_background(color);
+    }
+    builder.scale(opt.scale);
+    if let Some(lines) = opt.head {
+        builder.head(lines);
+    }
+    if let Some(lines) = opt.tail {
+        builder.tail(lines);
+    }
+    if let Some(lines) = opt.vscale {
+        builder.vscale(lines);
+    }
+    if let Some(width) = opt.pad_to {
+        builder.pad_to(width);
+    }
+    if let Some(ramp) = opt.ramp {
+        builder.ramp(&ramp);
+    }
+    builder.eighth_blocks(opt.eighth_blocks);
+    if opt.only_comments {
+        builder.scope_filter(ScopeFilter::Comments);
+    } else if opt.only_code {
+        builder.scope_filter(ScopeFilter::Code);
+    }
+    if opt.rainbow_depth {
+        if opt.depth_palette.is_empty() {
+            builder.rainbow_depth(&printer::DEFAULT_DEPTH_PALETTE);
+        } else {
+            builder.rainbow_depth(&opt.depth_palette);
+        }
+    }
+    if let Some((top, bottom)) = opt.gradient {
+        builder.gradient(top, bottom);
+    }
+    if let Some(glyph) = opt.control_glyph {
+        builder.control_glyph(glyph);
     }
-    if let Some(columns) = opt.columns {
-        builder.columns(columns);
+    if let Some(delimiter) = &opt.split_on {
+        builder.split_on(delimiter);
+    }
+    if let Some(count) = opt.palette_summary {
+        builder.palette_summary(count.unwrap_or(8));
+    }
+    builder
+        .tint_by_file(opt.tint_by_file)
+        .rtl(opt.rtl)
+        .rotate(opt.rotate);
+    if let Some(width) = opt.with_source {
+        builder.with_source(width.unwrap_or(80));
+    }
+    if let Some(count) = opt.mark_longest {
+        builder.mark_longest(count);
+    }
+    for &line in &opt.highlight_line {
+        builder.highlight_line(line);
+    }
+    if let Some(path) = &opt.annotate {
+        for (line, color, label) in read_annotations(path)? {
+            builder.annotate(line, color, label.as_deref());
+        }
+    }
+    if let Some(path) = &opt.diff {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read {}: {}", path.display(), err))?;
+        builder.diff(&text);
+    }
+    if let Some(column) = opt.guide {
+        builder.guide(column).guide_ticks(opt.guide_ticks);
+    }
+    if let Some((left, right)) = opt.edges {
+        builder.edges(left, right);
+    }
+    if let Some(lang) = &opt.language {
+        builder.language(lang);
+    }
+    if let Some(lang) = opt.stdin_language {
+        builder.stdin_language(&lang);
+    }
+    for (extension, language) in &opt.map_ext {
+        builder.map_extension(extension, language);
+    }
+    match opt.columns {
+        Some(Columns::Fixed(columns)) => {
+            builder.columns(columns);
+        }
+        // independent of the terminal, except with --edges which limits the width itself
+        _ if opt.deterministic && opt.edges.is_none() => {
+            builder.columns(DETERMINISTIC_COLUMNS);
+        }
+        // lines are left untruncated when not writing to a terminal
+        Some(Columns::Auto) if io::stdout().is_tty() => {
+            if let Some(width) = terminal_width() {
+                builder.columns(width);
+            }
+        }
+        _ => {}
+    }
+    if let Some(percent) = opt.columns_percent {
+        let width = terminal_width()
+            .ok_or_else(|| anyhow!("--columns-percent requires a terminal or $COLUMNS"))?;
+        builder.columns((width * percent / 100).max(1));
     }
     if let Some(tabs) = opt.tabs {
         builder.tabs(tabs);
     }
+    if !opt.tab_stops.is_empty() {
+        builder.tab_stops(&opt.tab_stops);
+    }
+
+    #[cfg(feature = "follow")]
+    if opt.follow {
+        if opt.file.len() != 1 || opt.file[0].as_os_str() == "-" {
+            return Err(anyhow!("--follow requires exactly one file"));
+        }
+        builder.follow(true);
+    }
+
+    #[cfg(feature = "image")]
+    if let Some(protocol) = opt.image {
+        let detected = if opt.deterministic {
+            None
+        } else {
+            image::ImageProtocol::detect()
+        };
+        let protocol = protocol
+            .or(detected)
+            .ok_or_else(|| {
+                anyhow!("the terminal does not seem to support inline images; specify --image=iterm2 or --image=kitty")
+            })?;
+        builder.image(protocol);
+    }
 
-    let printer = builder.build();
+    let printer = builder.try_build()?;
     let mut stdout = io::stdout();
 
-    let files = if opt.file.is_empty() {
+    // colors are written only to files with --output-dir
+    let colored_stdout = color_mode != ColorMode::NoColor && opt.output_dir.is_none();
+    #[cfg(unix)]
+    restore_on_interrupt(opt.tui, colored_stdout)?;
+    let _guard = if colored_stdout {
+        Some(ResetGuard)
+    } else {
+        None
+    };
+
+    if opt.legend_only {
+        printer.print_legend(&mut stdout)?;
+        return Ok(());
+    }
+
+    let screen = if opt.tui {
+        Some(AlternateScreen::enter()?)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "git")]
+    let revs: Vec<_> = opt.rev.into_iter().map(InputSource::Rev).collect();
+    #[cfg(not(feature = "git"))]
+    let revs = Vec::new();
+
+    let files = if opt.file.is_empty() && revs.is_empty() && !opt.theme_preview {
         vec![PathBuf::from("-")]
+    } else if let Some(since) = opt.since {
+        let cutoff = SystemTime::now()
+            .checked_sub(since)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let count = opt.file.len();
+        // files whose modification times are unknown are kept to report errors on them
+        let files: Vec<_> = opt
+            .file
+            .into_iter()
+            .filter(|file| {
+                file.as_os_str() == "-"
+                    || std::fs::metadata(file)
+                        .and_then(|metadata| metadata.modified())
+                        .map_or(true, |modified| modified >= cutoff)
+            })
+            .collect();
+        if files.len() < count {
+            warn(format!(
+                "skipped {} files not modified within --since",
+                count - files.len()
+            ));
+        }
+        files
     } else {
         opt.file
     };
-    for file in files {
-        if file == PathBuf::from("-") {
-            let stdin = io::stdin();
-            let mut stdin = stdin.lock();
-            printer.print_from_reader(&mut stdout, &mut stdin)?;
+    // opening a broken or circular link fails with an error that does not name it
+    let files: Vec<_> = files
+        .into_iter()
+        .filter(|file| {
+            let is_link = std::fs::symlink_metadata(file)
+                .is_ok_and(|metadata| metadata.file_type().is_symlink());
+            match std::fs::metadata(file) {
+                Err(err) if is_link => {
+                    warn(format!(
+                        "skipped broken symbolic link {}: {}",
+                        file.display(),
+                        err
+                    ));
+                    false
+                }
+                _ => true,
+            }
+        })
+        .collect();
+    let samples = if opt.theme_preview {
+        THEME_PREVIEW
+    } else {
+        &[]
+    };
+    let inputs = files
+        .into_iter()
+        .map(InputSource::from_path)
+        .chain(revs)
+        .chain(
+            samples
+                .iter()
+                .map(|&(name, text)| InputSource::Static { name, text }),
+        );
+
+    if opt.validate {
+        return validate_inputs(&printer, inputs, opt.language.is_some());
+    }
+    if opt.detect_indent_and_report {
+        return report_indentation(inputs);
+    }
+
+    let started = Instant::now();
+    if opt.count_only {
+        print_line_counts(&printer, inputs)?;
+        if opt.profile {
+            print_profile(profile, printer.timings(), started.elapsed());
+        }
+        return Ok(());
+    }
+
+    let separator = opt.separator.unwrap_or(if opt.concat {
+        Separator::Line
+    } else if opt.theme_preview {
+        Separator::Blank
+    } else {
+        Separator::None
+    });
+    if let Some(dir) = &opt.output_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|err| anyhow!("failed to create {}: {}", dir.display(), err))?;
+    }
+    let extension = if color_mode == ColorMode::NoColor {
+        "txt"
+    } else {
+        "ans"
+    };
+    let mut output_names = HashSet::new();
+
+    // inputs with their languages when grouped, which are detected before printing any
+    let inputs: Vec<(Option<&str>, InputSource)> = if opt.group_by_extension {
+        let mut inputs: Vec<_> = inputs
+            .map(|mut input| (Some(detect_language(&printer, &mut input)), input))
+            .collect();
+        inputs.sort_by_key(|(language, _)| *language);
+        inputs
+    } else {
+        inputs.map(|input| (None, input)).collect()
+    };
+
+    let mut total = Dimensions::default();
+    // timings of the printers built for --fit
+    let mut fitted_timings = Vec::new();
+    // not shown on the terminal the minimaps go to, where it would get between rows
+    let progress = (inputs.len() > 1
+        && !opt.quiet
+        && io::stderr().is_tty()
+        && (opt.output_dir.is_some() || !io::stdout().is_tty()))
+    .then(|| Progress::new(inputs.len()));
+    let mut previous_language = None;
+    for (i, (language, input)) in inputs.into_iter().enumerate() {
+        if let Some(progress) = &progress {
+            progress.update(i, &input);
+        }
+
+        // rendered into memory with --output-dir, so that no file is left for an input
+        // that fails
+        let mut rendered = Vec::new();
+        let mut writer: &mut dyn Write = if opt.output_dir.is_some() {
+            &mut rendered
         } else {
-            printer.print_file(&mut stdout, file)?;
+            &mut stdout
+        };
+
+        if i > 0 {
+            match separator {
+                Separator::None => {}
+                Separator::Line => {
+                    printer.print_separator(&mut writer, total.columns)?;
+                    total.rows += 1;
+                }
+                Separator::Blank => {
+                    printer.print_blank_row(&mut writer)?;
+                    total.rows += 1;
+                }
+            }
+        }
+        if let Some(language) = language.filter(|language| Some(*language) != previous_language) {
+            let width = printer.print_heading(&mut writer, language)?;
+            total.rows += 1;
+            total.columns = total.columns.max(width);
+            previous_language = Some(language);
+        }
+
+        let dims = match opt.fit {
+            Some((columns, rows)) => {
+                let rows = rows.saturating_sub(opt.first_line_as_title as usize).max(1);
+                let (dims, timings) = print_fitted(
+                    &printer,
+                    &mut builder,
+                    &mut writer,
+                    &input,
+                    (columns, rows),
+                    i,
+                )?;
+                fitted_timings.push(timings);
+                dims
+            }
+            None => printer.print_source(&mut writer, &input)?,
+        };
+        if let Some(dir) = &opt.output_dir {
+            let path = dir.join(output_name(&input, extension, &mut output_names));
+            std::fs::write(&path, rendered)
+                .map_err(|err| anyhow!("failed to write {}: {}", path.display(), err))?;
+        }
+
+        total.rows += dims.rows;
+        total.columns = total.columns.max(dims.columns);
+        if !opt.concat && opt.print_dims {
+            stdout.flush()?;
+            eprintln!("rows={} cols={}", dims.rows, dims.columns);
         }
     }
 
+    if opt.concat && opt.print_dims {
+        stdout.flush()?;
+        eprintln!("rows={} cols={}", total.rows, total.columns);
+    }
+
+    if opt.profile {
+        stdout.flush()?;
+        let timings = fitted_timings
+            .into_iter()
+            .fold(printer.timings(), |mut timings, fitted| {
+                timings.detection += fitted.detection;
+                timings.highlighting += fitted.highlighting;
+                timings
+            });
+        print_profile(profile, timings, started.elapsed());
+    }
+
+    if let Some(screen) = screen {
+        screen.wait_for_quit()?;
+    }
+
     Ok(())
 }
 
-fn true_color_is_enabled() -> bool {
-    std::env::var("COLORTERM")
-        .map(|colorterm| match &colorterm[..] {
-            "truecolor" | "24bit" => true,
-            _ => false,
-        })
-        .unwrap_or(false)
+// contrast ratio of the theme's foreground against the terminal's background below
+// which --theme-auto-contrast steps in, that of large text in WCAG
+const MIN_CONTRAST: f32 = 3.0;
+
+// the width of lines rendered with --deterministic unless --columns is given
+const DETERMINISTIC_COLUMNS: usize = 80;
+
+// rendered with --theme-preview, covering the usual kinds of tokens in a few languages
+const THEME_PREVIEW: &[(&str, &str)] = &[
+    (
+        "preview.rs",
+        r#"// Counts the words in each line.
+use std::io::{self, BufRead};
+
+fn main() -> io::Result<()> {
+    let mut total = 0;
+    for line in io::stdin().lock().lines() {
+        let words = line?.split_whitespace().count();
+        total += words;
+        println!("{:>4} words", words);
+    }
+    eprintln!("total: {}", total);
+    Ok(())
+}
+"#,
+    ),
+    (
+        "preview.py",
+        r#"# Prints the first Fibonacci numbers.
+def fibonacci(n: int) -> list:
+    '''Returns the first n numbers.'''
+    numbers = [0, 1]
+    while len(numbers) < n:
+        numbers.append(numbers[-1] + numbers[-2])
+    return numbers[:n]
+
+if __name__ == "__main__":
+    print(", ".join(str(x) for x in fibonacci(10)))
+"#,
+    ),
+    (
+        "preview.html",
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <!-- a page with a single link -->
+  <body class="main" style="margin: 0 auto">
+    <a href="https://example.com/">Example</a>
+  </body>
+</html>
+"#,
+    ),
+];
+
+/// Prints a table of languages sorted by the number of lines, followed by the total.
+/// Inputs that cannot be counted are left out with a warning.
+fn print_line_counts<I>(printer: &Printer, inputs: I) -> Result<()>
+where
+    I: IntoIterator<Item = InputSource>,
+{
+    // language -> (files, lines), where a notebook is a file of each language in it
+    let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+    let mut files = 0;
+    for input in inputs {
+        let languages = match printer.count_lines_by_language(&input) {
+            Ok(languages) => languages,
+            Err(err) => {
+                let name = input
+                    .path_hint()
+                    .map_or("<stdin>".into(), Path::to_string_lossy);
+                warn(format!("skipping {}: {}", name, err));
+                continue;
+            }
+        };
+        for (language, lines) in languages {
+            let count = counts.entry(language).or_default();
+            count.0 += 1;
+            count.1 += lines;
+        }
+        files += 1;
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|(a_name, (_, a_lines)), (b_name, (_, b_lines))| {
+        b_lines.cmp(a_lines).then(a_name.cmp(b_name))
+    });
+    let lines: usize = counts.iter().map(|(_, (_, lines))| lines).sum();
+
+    let width = counts
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(std::iter::once("Language".len()))
+        .max()
+        .unwrap_or(0);
+    let mut stdout = io::stdout();
+    let mut row = |name: &str, files: &dyn Display, lines: &dyn Display| {
+        writeln!(
+            stdout,
+            "{:<width$} {:>8} {:>10}",
+            name,
+            files,
+            lines,
+            width = width
+        )
+    };
+    row("Language", &"Files", &"Lines")?;
+    for (name, (files, lines)) in &counts {
+        row(name, files, lines)?;
+    }
+    row("Total", &files, &lines)?;
+    Ok(())
+}
+
+/// Prints whether each input can be rendered, failing if any cannot. Inputs in plain
+/// text are reported as undetected unless the language is given explicitly with
+/// `explicit_language`, but do not fail.
+fn validate_inputs<I>(printer: &Printer, inputs: I, explicit_language: bool) -> Result<()>
+where
+    I: IntoIterator<Item = InputSource>,
+{
+    let mut stdout = io::stdout();
+    let mut total = 0;
+    let mut failed = 0;
+    for input in inputs {
+        let name = input
+            .path_hint()
+            .map_or("<stdin>".into(), Path::to_string_lossy)
+            .into_owned();
+        let (status, detail) = match printer.count_lines(&input) {
+            Ok(("Plain Text", _)) if !explicit_language => ("undetected", String::new()),
+            Ok((language, _)) => ("ok", format!(" ({})", language)),
+            Err(err) => error_status(&err),
+        };
+        writeln!(stdout, "{:<10} {}{}", status, name, detail)?;
+        total += 1;
+        if status != "ok" && status != "undetected" {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} inputs cannot be rendered", failed, total));
+    }
+    Ok(())
+}
+
+/// The status reported for an input that cannot be read, with a detail to follow its name.
+fn error_status(err: &anyhow::Error) -> (&'static str, String) {
+    match err.downcast_ref::<io::Error>() {
+        Some(err) if err.kind() == io::ErrorKind::InvalidData => ("binary", String::new()),
+        _ => ("error", format!(" ({})", err)),
+    }
+}
+
+/// Prints the indentation style of each input, failing if any has lines deviating
+/// from its style or cannot be read.
+fn report_indentation<I>(inputs: I) -> Result<()>
+where
+    I: IntoIterator<Item = InputSource>,
+{
+    let mut stdout = io::stdout();
+    let mut total = 0;
+    let mut failed = 0;
+    for input in inputs {
+        let name = input
+            .path_hint()
+            .map_or("<stdin>".into(), Path::to_string_lossy)
+            .into_owned();
+        total += 1;
+        let lines = input
+            .open()
+            .and_then(|reader| Ok(reader.lines().collect::<io::Result<Vec<_>>>()?));
+        let lines = match lines {
+            Ok(lines) => lines,
+            Err(err) => {
+                let (status, detail) = error_status(&err);
+                writeln!(stdout, "{:<10} {}{}", status, name, detail)?;
+                failed += 1;
+                continue;
+            }
+        };
+        let report = indent::detect_indent(lines.iter().map(String::as_str));
+
+        let style = match report.style {
+            Some(IndentStyle::Tabs) => "tabs".to_string(),
+            Some(IndentStyle::Spaces(width)) => format!("spaces:{}", width),
+            None => "none".to_string(),
+        };
+        let detail = if report.deviating_lines > 0 {
+            failed += 1;
+            format!(
+                " ({} of {} indented lines deviate)",
+                report.deviating_lines, report.indented_lines
+            )
+        } else {
+            String::new()
+        };
+        writeln!(stdout, "{:<10} {}{}", style, name, detail)?;
+    }
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} inputs are not indented consistently or cannot be read",
+            failed,
+            total
+        ));
+    }
+    Ok(())
+}
+
+/// Prints the time spent in each phase to stderr. `printing` is the time spent printing
+/// all the inputs, including the detection and the highlighting in `timings`.
+fn print_profile(mut phases: Vec<(&str, Duration)>, timings: Timings, printing: Duration) {
+    phases.push(("detection", timings.detection));
+    phases.push(("highlighting", timings.highlighting));
+    phases.push((
+        "rendering",
+        printing.saturating_sub(timings.detection + timings.highlighting),
+    ));
+    let total = phases.iter().map(|(_, time)| *time).sum::<Duration>();
+    phases.push(("total", total));
+    for (phase, time) in phases {
+        eprintln!("{:<12} {:>10.3} ms", phase, time.as_secs_f64() * 1000.0);
+    }
+}
+
+/// Prints the `index`th input with a printer built from `builder` to fit it in `columns`
+/// and `rows`, measuring it with `printer`. Returns its dimensions and the timings of the
+/// printer built.
+fn print_fitted<W: Write>(
+    printer: &Printer,
+    builder: &mut PrinterBuilder,
+    writer: &mut W,
+    input: &InputSource,
+    (columns, rows): (usize, usize),
+    index: usize,
+) -> Result<(Dimensions, Timings)> {
+    let mut data = Vec::new();
+    input.open()?.read_to_end(&mut data)?;
+    let (lines, width) = printer.measure(&data[..])?;
+    let fitted = builder
+        .columns(columns)
+        .scale(width.div_ceil(columns).max(1))
+        .sample_rate(lines.div_ceil(rows).max(1))
+        .try_build()?;
+    // built afresh for each input, so it picks up the tint where the batch is
+    fitted.set_inputs_printed(index);
+    let dims = fitted.print_from_reader_with_path(writer, &mut &data[..], input.path_hint())?;
+    Ok((dims, fitted.timings()))
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// whether the counter of Progress is on the last line of stderr, where a warning would
+// follow it
+static PROGRESS_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Detects the language of an input to group it under, falling back to plain text if
+/// the input cannot be read, which printing it reports again.
+fn detect_language(printer: &Printer, input: &mut InputSource) -> &'static str {
+    printer.detect_language(input).unwrap_or_else(|err| {
+        let name = input
+            .path_hint()
+            .map_or("<stdin>".into(), Path::to_string_lossy);
+        warn(format!(
+            "failed to detect the language of {}: {}",
+            name, err
+        ));
+        &printer::detect_syntax(None, None, None).name
+    })
+}
+
+/// Names the file for the minimap of `input` written with --output-dir after its base
+/// name, numbering names already in `taken`.
+fn output_name(input: &InputSource, extension: &str, taken: &mut HashSet<String>) -> String {
+    let stem = input
+        .path_hint()
+        .and_then(Path::file_name)
+        .map_or("stdin".into(), |name| name.to_string_lossy());
+    let mut name = format!("{}.{}", stem, extension);
+    let mut count = 1;
+    while !taken.insert(name.clone()) {
+        count += 1;
+        name = format!("{}-{}.{}", stem, count, extension);
+    }
+    name
+}
+
+/// A `[n/total] name` counter of the inputs being printed, rewritten in place on stderr
+/// and cleared when dropped.
+struct Progress {
+    total: usize,
+    // width of the terminal, beyond which the counter would wrap
+    columns: usize,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        let columns = crossterm::terminal::size().map_or(usize::MAX, |(columns, _)| columns.into());
+        Self { total, columns }
+    }
+
+    fn update(&self, index: usize, input: &InputSource) {
+        let name = input
+            .path_hint()
+            .map_or("<stdin>".into(), Path::to_string_lossy);
+        let line = format!("[{}/{}] {}", index + 1, self.total, name);
+        let mut width = 0;
+        let line: String = line
+            .chars()
+            .take_while(|c| {
+                width += c.width().unwrap_or(0);
+                width < self.columns
+            })
+            .collect();
+        eprint!("\r\x1b[K{}", line);
+        PROGRESS_SHOWN.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        eprint!("\r\x1b[K");
+        PROGRESS_SHOWN.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Prints a warning to stderr unless --quiet is given, clearing the progress counter
+/// first, which the next input shows again.
+fn warn<T: Display>(message: T) {
+    if !QUIET.load(Ordering::Relaxed) {
+        if PROGRESS_SHOWN.swap(false, Ordering::Relaxed) {
+            eprint!("\r\x1b[K");
+        }
+        eprintln!("warning: {}", message);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(anyhow!("expected auto, always, or never")),
+        }
+    }
+}
+
+impl ColorChoice {
+    fn is_enabled(self) -> bool {
+        self.is_enabled_in(|name| std::env::var_os(name), || io::stdout().is_tty())
+    }
+
+    /// Like `is_enabled`, but with the environment variables looked up with `env` and
+    /// whether stdout is a terminal told by `is_tty`.
+    fn is_enabled_in(
+        self,
+        env: impl Fn(&str) -> Option<OsString>,
+        is_tty: impl FnOnce() -> bool,
+    ) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                let var = |name| env(name).filter(|value| !value.is_empty());
+                if var("NO_COLOR").is_some() {
+                    false
+                } else if var("CLICOLOR_FORCE").is_some_and(|value| value != "0") {
+                    true
+                } else if var("CLICOLOR").is_some_and(|value| value == "0") {
+                    false
+                } else if cfg!(unix) && var("TERM").is_none_or(|term| term == "dumb") {
+                    // terminals without capabilities, which Windows consoles do not declare
+                    false
+                } else {
+                    is_tty()
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Columns {
+    Auto,
+    Fixed(usize),
+}
+
+impl FromStr for Columns {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            _ => s
+                .parse()
+                .map(Self::Fixed)
+                .map_err(|_| anyhow!("expected a number or auto")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Separator {
+    None,
+    Line,
+    Blank,
+}
+
+impl FromStr for Separator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "line" => Ok(Self::Line),
+            "blank" => Ok(Self::Blank),
+            _ => Err(anyhow!("expected none, line, or blank")),
+        }
+    }
+}
+
+/// Resets colors when dropped, so that an error or a panic in the middle of
+/// a line does not leave the terminal colored.
+struct ResetGuard;
+
+impl Drop for ResetGuard {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(io::stdout(), crossterm::style::ResetColor);
+    }
+}
+
+/// Restores the terminal and exits on SIGINT or SIGTERM.
+#[cfg(unix)]
+fn restore_on_interrupt(alternate_screen: bool, colored: bool) -> Result<()> {
+    use signal_hook::iterator::Signals;
+
+    let signals = Signals::new([signal_hook::SIGINT, signal_hook::SIGTERM])?;
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            let mut stdout = io::stdout();
+            if colored {
+                let _ = crossterm::execute!(stdout, crossterm::style::ResetColor);
+            }
+            if alternate_screen {
+                let _ = crossterm::terminal::disable_raw_mode();
+                let _ = crossterm::execute!(stdout, crossterm::terminal::LeaveAlternateScreen);
+            }
+            std::process::exit(128 + signal);
+        }
+    });
+
+    Ok(())
+}
+
+fn terminal_width() -> Option<usize> {
+    crossterm::terminal::size()
+        .ok()
+        .map(|(columns, _)| columns as usize)
+        .filter(|columns| *columns > 0)
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+}
+
+/// The terminal's background color from $COLORFGBG (e.g. "15;0"), whose last field is
+/// the index of the background in the 16-color palette.
+fn terminal_background() -> Option<Color> {
+    let var = std::env::var("COLORFGBG").ok()?;
+    let index: u8 = var.rsplit(';').next()?.parse().ok()?;
+    if index >= 16 {
+        return None;
+    }
+    let (r, g, b) = ansi_colours::rgb_from_ansi256(index);
+    Some(Color { r, g, b, a: 0xff })
+}
+
+fn parse_edges(s: &str) -> Result<(usize, usize)> {
+    let (left, right) = s.split_once(':').ok_or_else(|| anyhow!("expected L:R"))?;
+    Ok((left.parse()?, right.parse()?))
+}
+
+fn parse_map_ext(s: &str) -> Result<(String, String)> {
+    let (extension, language) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected ext=lang"))?;
+    let extension = extension.trim_start_matches('.');
+    if extension.is_empty() || language.is_empty() {
+        return Err(anyhow!("expected ext=lang"));
+    }
+    Ok((extension.to_string(), language.to_string()))
+}
+
+fn parse_duration(s: &str) -> Result<Duration> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err(anyhow!("expected a number followed by s, m, h, d, or w")),
+    };
+    let count: u64 = s[..s.len() - 1].parse()?;
+    Ok(Duration::from_secs(count.saturating_mul(unit)))
+}
+
+fn parse_fit(s: &str) -> Result<(usize, usize)> {
+    let (width, height) = s.split_once('x').ok_or_else(|| anyhow!("expected WxH"))?;
+    let (width, height) = (width.parse()?, height.parse()?);
+    if width == 0 || height == 0 {
+        return Err(anyhow!("width and height must be at least 1"));
+    }
+    Ok((width, height))
+}
+
+fn parse_recolor(s: &str) -> Result<(ScopeSelectors, Color)> {
+    let (scope, color) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow!("expected scope=color"))?;
+    Ok((parse_scope(scope)?, parse_color(color)?))
+}
+
+fn parse_scope(s: &str) -> Result<ScopeSelectors> {
+    s.parse().map_err(|_| anyhow!("invalid scope: {}", s))
+}
+
+fn parse_column_unit(s: &str) -> Result<ColumnUnit> {
+    match s {
+        "cells" => Ok(ColumnUnit::Cells),
+        "chars" => Ok(ColumnUnit::Chars),
+        _ => Err(anyhow!("expected cells or chars")),
+    }
+}
+
+fn parse_pad_to(s: &str) -> Result<PadWidth> {
+    match s {
+        "auto" => Ok(PadWidth::Widest),
+        _ => s
+            .parse()
+            .map(PadWidth::Columns)
+            .map_err(|_| anyhow!("expected a number of columns or auto")),
+    }
+}
+
+fn parse_row_terminator(s: &str) -> Result<RowTerminator> {
+    match s {
+        "lf" => Ok(RowTerminator::Lf),
+        "crlf" => Ok(RowTerminator::CrLf),
+        "none" => Ok(RowTerminator::None),
+        _ => Err(anyhow!("expected lf, crlf, or none")),
+    }
+}
+
+fn read_annotations(path: &Path) -> Result<Vec<(usize, Color, Option<String>)>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("failed to read {}: {}", path.display(), err))?;
+
+    let mut annotations = Vec::new();
+    for (i, entry) in content.lines().enumerate() {
+        let entry = entry.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || anyhow!("{}:{}: expected line:color[:label]", path.display(), i + 1);
+        let mut fields = entry.splitn(3, ':');
+        let line = fields
+            .next()
+            .and_then(|line| line.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        let color = fields
+            .next()
+            .and_then(|color| color.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        let label = fields.next().map(|label| label.trim().to_string());
+        annotations.push((line, color, label));
+    }
+    Ok(annotations)
+}
+
+fn parse_gradient(s: &str) -> Result<(Color, Color)> {
+    let (top, bottom) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow!("expected top,bottom"))?;
+    Ok((parse_color(top)?, parse_color(bottom)?))
+}
+
+fn parse_color(s: &str) -> Result<Color> {
+    s.parse().map_err(|_| anyhow!("invalid color: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_of_unreadable_input_falls_back_to_plain_text() {
+        let printer = PrinterBuilder::new().build();
+        let mut input = InputSource::File("does-not-exist.rs".into());
+        assert!(printer.detect_language(&mut input).is_err());
+        assert_eq!(detect_language(&printer, &mut input), "Plain Text");
+    }
+
+    #[test]
+    fn fitted_inputs_are_tinted_by_file() {
+        let mut builder = PrinterBuilder::new();
+        builder.color_mode(ColorMode::TrueColor).tint_by_file(true);
+        let printer = builder.build();
+        let inputs = [
+            InputSource::Static {
+                name: "a.rs",
+                text: "fn main() {}\n",
+            },
+            InputSource::Static {
+                name: "b.rs",
+                text: "fn main() {}\n",
+            },
+        ];
+        let rendered: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let mut writer = Vec::new();
+                print_fitted(&printer, &mut builder, &mut writer, input, (10, 5), i).unwrap();
+                writer
+            })
+            .collect();
+        assert_ne!(rendered[0], rendered[1]);
+    }
+
+    /// Whether `choice` enables colors on a terminal with only the variables `vars` set.
+    fn colors_enabled(choice: ColorChoice, vars: &[(&str, &str)]) -> bool {
+        colors_enabled_on(choice, vars, true)
+    }
+
+    /// Like `colors_enabled`, but telling whether stdout is a terminal with `is_tty`.
+    fn colors_enabled_on(choice: ColorChoice, vars: &[(&str, &str)], is_tty: bool) -> bool {
+        let env = |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.into())
+        };
+        choice.is_enabled_in(env, || is_tty)
+    }
+
+    #[test]
+    fn colors_are_enabled_on_terminals() {
+        let vars = [("TERM", "xterm")];
+        assert!(colors_enabled(ColorChoice::Auto, &vars));
+        assert!(!colors_enabled_on(ColorChoice::Auto, &vars, false));
+        assert!(colors_enabled_on(ColorChoice::Always, &vars, false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn colors_are_disabled_on_dumb_terminals() {
+        assert!(!colors_enabled(ColorChoice::Auto, &[("TERM", "dumb")]));
+        assert!(!colors_enabled(ColorChoice::Auto, &[]));
+        assert!(colors_enabled(ColorChoice::Always, &[("TERM", "dumb")]));
+    }
+
+    #[test]
+    fn no_color_disables_colors_unless_always() {
+        let vars = [("TERM", "xterm"), ("NO_COLOR", "1")];
+        assert!(!colors_enabled(ColorChoice::Auto, &vars));
+        assert!(colors_enabled(ColorChoice::Always, &vars));
+        assert!(colors_enabled(
+            ColorChoice::Auto,
+            &[("TERM", "xterm"), ("NO_COLOR", "")]
+        ));
+    }
+
+    #[test]
+    fn parse_columns() {
+        assert_eq!("auto".parse::<Columns>().unwrap(), Columns::Auto);
+        assert_eq!("80".parse::<Columns>().unwrap(), Columns::Fixed(80));
+        for invalid in ["", "-1", "8O", "Auto"] {
+            assert!(invalid.parse::<Columns>().is_err(), "{:?}", invalid);
+        }
+    }
 }
@@ -0,0 +1,46 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+/// A cell of a Jupyter notebook.
+pub struct NotebookCell {
+    /// Language of the cell as named in the notebook (e.g. python), if known
+    pub language: Option<String>,
+    pub source: String,
+}
+
+/// Extracts the cells of a notebook in the .ipynb format.
+///
+/// Code cells are in the language of the notebook's kernel and markdown cells in
+/// Markdown. The language of raw cells is unknown.
+pub fn parse_notebook(data: &[u8]) -> Result<Vec<NotebookCell>> {
+    let notebook: Value = serde_json::from_slice(data)?;
+    let metadata = &notebook["metadata"];
+    let code_language = metadata["language_info"]["name"]
+        .as_str()
+        .or_else(|| metadata["kernelspec"]["language"].as_str());
+
+    let cells = notebook["cells"]
+        .as_array()
+        .ok_or_else(|| anyhow!("no cells in notebook"))?;
+    let cells = cells
+        .iter()
+        .map(|cell| {
+            let language = match cell["cell_type"].as_str() {
+                Some("code") => code_language,
+                Some("markdown") => Some("markdown"),
+                _ => None,
+            };
+            // either a string or a list of lines with their newlines
+            let source = match &cell["source"] {
+                Value::String(source) => source.clone(),
+                Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+                _ => String::new(),
+            };
+            NotebookCell {
+                language: language.map(str::to_string),
+                source,
+            }
+        })
+        .collect();
+    Ok(cells)
+}
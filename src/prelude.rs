@@ -0,0 +1,4 @@
+//! The types needed for rendering minimaps, to be glob imported.
+
+pub use crate::input::InputSource;
+pub use crate::printer::{ColorMode, Dimensions, Printer, PrinterBuilder, RenderConfig};
@@ -1,272 +1,3847 @@
-use anyhow::Result;
-use crossterm::style::{self, Color};
+use crate::ansi;
+use crate::diff::{LineChange, LineDiff};
+use crate::highlight::{HighlightStart, LineHighlighter, Region};
+#[cfg(feature = "image")]
+use crate::image::{self, ImageProtocol, Raster};
+use crate::input::InputSource;
+#[cfg(feature = "notebook")]
+use crate::notebook;
+use crate::theme;
+use anyhow::{anyhow, Result};
+use crossterm::style::{self, Attribute, Color};
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::ffi::OsString;
+use std::io::{self, BufRead, Write};
 use std::path::Path;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Theme, ThemeSet};
-use syntect::parsing::SyntaxSet;
+use std::time::{Duration, Instant};
+use syntect::highlighting::{ScopeSelectors, Style, Theme};
+use syntect::parsing::{Scope, ScopeStack, SyntaxReference, SyntaxSet};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 lazy_static! {
-    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_nonewlines();
-    static ref THEME: Theme = {
-        static DEFAULT_THEME_FILE: &[u8] =
-            include_bytes!("../themes/sublime-monokai-extended/Monokai Extended.tmTheme");
-
-        let mut reader = io::Cursor::new(DEFAULT_THEME_FILE);
-        ThemeSet::load_from_reader(&mut reader).unwrap_or_else(|_| {
-            let theme_set = ThemeSet::load_defaults();
-            theme_set.themes["base16-ocean.dark"].clone()
-        })
-    };
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref COMMENT: ScopeSelectors = "comment".parse().unwrap();
+}
+
+// background of non-whitespace cells on the guide
+const GUIDE_COLOR: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0x50,
+    g: 0x50,
+    b: 0x50,
+    a: 0xff,
+};
+
+// brightness of lines other than the highlighted ones
+const DIMMED_BRIGHTNESS: f32 = 0.35;
+
+// drawn at indentation levels
+const INDENT_GUIDE: char = '│';
+
+// drawn in place of blank lines with blank_marker
+const BLANK_MARKER: char = '▁';
+
+// marks control characters
+const CONTROL_COLOR: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0xff,
+    g: 0x00,
+    b: 0xff,
+    a: 0xff,
+};
+
+// marks lines exceeding the guide
+const TICK_COLOR: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0xff,
+    g: 0x00,
+    b: 0x00,
+    a: 0xff,
+};
+
+// mark lines added, changed, and lines removed, with diff
+const DIFF_ADDED_COLOR: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0x50,
+    g: 0xc8,
+    b: 0x50,
+    a: 0xff,
+};
+const DIFF_CHANGED_COLOR: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0xd0,
+    g: 0xb0,
+    b: 0x40,
+    a: 0xff,
+};
+const DIFF_REMOVED_COLOR: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0xe0,
+    g: 0x50,
+    b: 0x50,
+    a: 0xff,
+};
+
+// backgrounds of lines added and changed, with diff
+const DIFF_ADDED_BACKGROUND: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0x1e,
+    g: 0x3a,
+    b: 0x1e,
+    a: 0xff,
+};
+const DIFF_CHANGED_BACKGROUND: syntect::highlighting::Color = syntect::highlighting::Color {
+    r: 0x3a,
+    g: 0x34,
+    b: 0x1a,
+    a: 0xff,
+};
+
+// how often a followed input is checked for new lines
+#[cfg(feature = "follow")]
+const FOLLOW_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+// pixels per cell in images, with ink in the upper half of rows like the usual glyph
+#[cfg(feature = "image")]
+const IMAGE_CELL_WIDTH: usize = 2;
+#[cfg(feature = "image")]
+const IMAGE_ROW_HEIGHT: usize = 4;
+
+/// Colors cycled through by `PrinterBuilder::rainbow_depth` unless specified.
+pub const DEFAULT_DEPTH_PALETTE: [syntect::highlighting::Color; 3] = [
+    syntect::highlighting::Color {
+        r: 0xff,
+        g: 0xd7,
+        b: 0x00,
+        a: 0xff,
+    },
+    syntect::highlighting::Color {
+        r: 0xda,
+        g: 0x70,
+        b: 0xd6,
+        a: 0xff,
+    },
+    syntect::highlighting::Color {
+        r: 0x17,
+        g: 0x9f,
+        b: 0xff,
+        a: 0xff,
+    },
+];
+
+// hues blended into the colors of successive inputs with tint_by_file
+const TINT_PALETTE: [syntect::highlighting::Color; 6] = [
+    syntect::highlighting::Color {
+        r: 0xff,
+        g: 0x50,
+        b: 0x50,
+        a: 0xff,
+    },
+    syntect::highlighting::Color {
+        r: 0x50,
+        g: 0xd0,
+        b: 0x50,
+        a: 0xff,
+    },
+    syntect::highlighting::Color {
+        r: 0x50,
+        g: 0x90,
+        b: 0xff,
+        a: 0xff,
+    },
+    syntect::highlighting::Color {
+        r: 0xff,
+        g: 0xc0,
+        b: 0x30,
+        a: 0xff,
+    },
+    syntect::highlighting::Color {
+        r: 0xd0,
+        g: 0x50,
+        b: 0xff,
+        a: 0xff,
+    },
+    syntect::highlighting::Color {
+        r: 0x30,
+        g: 0xd0,
+        b: 0xd0,
+        a: 0xff,
+    },
+];
+
+// how much of the tint is blended into colors
+const TINT_STRENGTH: f32 = 0.3;
+
+// major kinds of scopes listed by `print_legend`, as named by most syntaxes
+const LEGEND_SCOPES: &[&str] = &[
+    "comment",
+    "string",
+    "constant.numeric",
+    "constant.language",
+    "constant.character.escape",
+    "keyword",
+    "keyword.operator",
+    "storage.type",
+    "storage.modifier",
+    "entity.name.function",
+    "entity.name.type",
+    "support.function",
+    "support.type",
+    "variable.parameter",
+    "punctuation",
+];
+
+/// Which regions to render.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeFilter {
+    /// Only comments
+    Comments,
+    /// Everything but comments
+    Code,
+}
+
+/// What ends each row of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowTerminator {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+    /// Nothing, for sinks laying out rows on their own
+    None,
+}
+
+/// What the maximum number of columns counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    /// Cells of the terminal, two of which wide characters take
+    Cells,
+    /// Characters, regardless of their widths
+    Chars,
+}
+
+/// Width that rows are padded to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadWidth {
+    /// A number of columns
+    Columns(usize),
+    /// The width of the widest row of each input
+    Widest,
+}
+
+/// How theme colors are encoded in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// 24-bit RGB colors
+    TrueColor,
+    /// Nearest colors in the xterm 256-color palette
+    Ansi256,
+    /// Nearest colors in the basic 16-color palette, which terminals let users customize
+    Ansi16,
+    /// No colors or other escape sequences at all
+    NoColor,
+}
+
+impl ColorMode {
+    /// True colors if `$COLORTERM` tells that the terminal supports them, or the
+    /// 256-color palette otherwise.
+    pub fn detect() -> Self {
+        let true_color = std::env::var("COLORTERM")
+            .map(|colorterm| matches!(&colorterm[..], "truecolor" | "24bit"))
+            .unwrap_or(false);
+        if true_color {
+            Self::TrueColor
+        } else {
+            Self::Ansi256
+        }
+    }
+}
+
+/// Options of a `Printer` other than its theme.
+///
+/// `PrinterBuilder` is the usual way to set them, with methods named after the fields
+/// unless noted. Build a `Printer` from a `RenderConfig` with `PrinterBuilder::with_config`.
+#[derive(Clone)]
+pub struct RenderConfig {
+    /// Language of all inputs, see `PrinterBuilder::language`
+    pub language: Option<String>,
+    /// Language of inputs without a path, see `PrinterBuilder::stdin_language`
+    pub stdin_language: Option<String>,
+    /// State highlighting starts from, see `PrinterBuilder::highlight_start`
+    pub highlight_start: Option<HighlightStart>,
+    /// Maximum number of columns of each row
+    pub columns: usize,
+    /// Tab width, with 0 passing tabs through
+    pub tabs: usize,
+    /// Columns tabs advance to, see `PrinterBuilder::tab_stops`
+    pub tab_stops: Vec<usize>,
+    /// Whether tabs are expanded to spaces
+    pub expand_tabs: bool,
+    /// How colors are encoded
+    pub color_mode: ColorMode,
+    /// Whether the name and the first line of each input are printed as a title
+    pub first_line_as_title: bool,
+    /// Colors replacing those of the theme for scopes, see `PrinterBuilder::recolor`
+    pub recolor: Vec<(ScopeSelectors, syntect::highlighting::Color)>,
+    /// Scopes left undimmed, see `PrinterBuilder::emphasize_scope`
+    pub emphasized_scopes: Vec<ScopeSelectors>,
+    /// Color of plain text instead of the theme's foreground
+    pub plain_color: Option<syntect::highlighting::Color>,
+    /// Factor adjusting the brightness of colors
+    pub brightness: f32,
+    /// Factor adjusting the contrast of colors
+    pub contrast: f32,
+    /// Numbers of columns kept at the left and the right of long lines
+    pub edges: Option<(usize, usize)>,
+    /// Number of source columns collapsed into each cell
+    pub scale: usize,
+    /// Only the first of every this many lines is rendered
+    pub sample_rate: usize,
+    /// Number of lines rendered from the start of each input
+    pub head: Option<usize>,
+    /// Number of lines rendered from the end of each input
+    pub tail: Option<usize>,
+    /// Number of source lines drawn in each row
+    pub vscale: usize,
+    /// Glyphs by increasing density, see `PrinterBuilder::ramp`
+    pub ramp: Vec<char>,
+    /// Whether cells are drawn with left eighth blocks, see `PrinterBuilder::eighth_blocks`
+    pub eighth_blocks: bool,
+    /// Column of the vertical guide
+    pub guide: Option<usize>,
+    /// Whether lines crossing the guide are marked in the gutter
+    pub guide_ticks: bool,
+    /// Whether the output is flushed after every row
+    pub flush_lines: bool,
+    /// Whether lines appended to files keep being rendered
+    #[cfg(feature = "follow")]
+    pub follow: bool,
+    /// Protocol of inline images to render minimaps as
+    #[cfg(feature = "image")]
+    pub image: Option<ImageProtocol>,
+    /// Colors of bracket nesting depths, see `PrinterBuilder::rainbow_depth`
+    pub rainbow_depth: Vec<syntect::highlighting::Color>,
+    /// Top and bottom colors of the background gradient
+    pub gradient: Option<(syntect::highlighting::Color, syntect::highlighting::Color)>,
+    /// Lines longer than this many bytes are not highlighted, unless it is 0
+    pub max_line_length: usize,
+    /// Whether rows are colored by line length instead of highlighting
+    pub heatmap: bool,
+    /// Whether long lines are wrapped instead of truncated
+    pub wrap: bool,
+    /// Which regions are rendered
+    pub scope_filter: Option<ScopeFilter>,
+    /// What ends each row
+    pub row_terminator: RowTerminator,
+    /// Width rows are padded to
+    pub pad_to: Option<PadWidth>,
+    /// What `columns` counts
+    pub column_unit: ColumnUnit,
+    /// Whether a single row of ink per column is printed instead
+    pub ink_profile: bool,
+    /// Glyph drawn for control characters
+    pub control_glyph: Option<char>,
+    /// Line numbers with gutter colors and labels, see `PrinterBuilder::annotate`
+    pub annotations: BTreeMap<usize, (syntect::highlighting::Color, Option<String>)>,
+    /// Number of colors in the palette summary printed after each input
+    pub palette_summary: Option<usize>,
+    /// Delimiter splitting inputs into parts, see `PrinterBuilder::split_on`
+    pub split_on: Option<String>,
+    /// Whether indentation levels are drawn as guides
+    pub indent_guides: bool,
+    /// Whether runs of whitespace are collapsed into one cell
+    pub compact_whitespace: bool,
+    /// Line numbers left undimmed, see `PrinterBuilder::highlight_line`
+    pub highlight_lines: BTreeSet<usize>,
+    /// Whether blank lines are marked
+    pub blank_marker: bool,
+    /// Whether lines failing to be highlighted are rendered as plain text
+    pub highlight_fallback: bool,
+    /// Rows of lines with fewer cells of ink than this are left blank
+    pub min_ink: usize,
+    /// Whether text is colored by escape sequences in inputs instead of highlighted
+    pub ansi_passthrough: bool,
+    /// Whether control characters from inputs are printed as they are
+    pub raw_control_chars: bool,
+    /// Background color of whitespace
+    pub whitespace_background: Option<syntect::highlighting::Color>,
+    /// Languages of file extensions, see `PrinterBuilder::map_extension`
+    pub extension_map: HashMap<String, String>,
+    /// Number of longest lines marked in the gutter
+    pub mark_longest: usize,
+    /// Whether each input is tinted with a different hue
+    pub tint_by_file: bool,
+    /// Whether rows are rendered right to left
+    pub rtl: bool,
+    /// Whether minimaps are rotated to run left to right
+    pub rotate: bool,
+    /// Whether the time spent in phases is measured
+    pub profile: bool,
+    /// Lines of the text that changes are marked against, see `PrinterBuilder::diff`
+    pub diff_base: Option<Vec<String>>,
+    /// Width of the source text printed beside the minimap
+    pub with_source: Option<usize>,
 }
 
 pub struct PrinterBuilder {
-    language: Option<String>,
-    columns: usize,
-    tabs: usize,
-    true_color: bool,
+    theme: Theme,
+    config: RenderConfig,
 }
 
-impl Default for PrinterBuilder {
+/// Tab width unless set with `PrinterBuilder::tabs`, for the command line and the library
+/// alike.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Lines longer than this many bytes are not highlighted by default.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+impl Default for RenderConfig {
     fn default() -> Self {
         Self {
             language: None,
+            stdin_language: None,
+            highlight_start: None,
             columns: usize::MAX,
-            tabs: 4,
-            true_color: false,
+            tabs: DEFAULT_TAB_WIDTH,
+            tab_stops: Vec::new(),
+            expand_tabs: true,
+            color_mode: ColorMode::Ansi256,
+            first_line_as_title: false,
+            recolor: Vec::new(),
+            emphasized_scopes: Vec::new(),
+            plain_color: None,
+            brightness: 1.0,
+            contrast: 1.0,
+            edges: None,
+            scale: 1,
+            sample_rate: 1,
+            head: None,
+            tail: None,
+            vscale: 1,
+            ramp: Vec::new(),
+            eighth_blocks: false,
+            guide: None,
+            guide_ticks: false,
+            flush_lines: false,
+            #[cfg(feature = "follow")]
+            follow: false,
+            #[cfg(feature = "image")]
+            image: None,
+            rainbow_depth: Vec::new(),
+            gradient: None,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            heatmap: false,
+            wrap: false,
+            scope_filter: None,
+            row_terminator: RowTerminator::Lf,
+            pad_to: None,
+            column_unit: ColumnUnit::Cells,
+            ink_profile: false,
+            control_glyph: None,
+            annotations: BTreeMap::new(),
+            palette_summary: None,
+            split_on: None,
+            indent_guides: false,
+            compact_whitespace: false,
+            highlight_lines: BTreeSet::new(),
+            blank_marker: false,
+            highlight_fallback: false,
+            min_ink: 0,
+            ansi_passthrough: false,
+            raw_control_chars: false,
+            whitespace_background: None,
+            extension_map: HashMap::new(),
+            mark_longest: 0,
+            tint_by_file: false,
+            rtl: false,
+            rotate: false,
+            profile: false,
+            diff_base: None,
+            with_source: None,
+        }
+    }
+}
+
+impl Default for PrinterBuilder {
+    fn default() -> Self {
+        Self {
+            theme: theme::default_theme().clone(),
+            config: Default::default(),
+        }
+    }
+}
+
+impl PrinterBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Starts from `config` with the default theme instead of the default options.
+    pub fn with_config(config: RenderConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// The options set so far.
+    pub fn config(&self) -> &RenderConfig {
+        &self.config
+    }
+
+    pub fn build(&self) -> Printer {
+        let tabs = if self.config.tabs > 0 {
+            self.config.tabs
+        } else {
+            DEFAULT_TAB_WIDTH
+        };
+        Printer {
+            config: RenderConfig {
+                scale: self.config.scale.max(1),
+                sample_rate: self.config.sample_rate.max(1),
+                vscale: self.config.vscale.max(1),
+                ..self.config.clone()
+            },
+            theme: self.theme.clone(),
+            tabs,
+            tab_stops: TabStops::new(&self.config.tab_stops, tabs),
+            expand_tabs: self.config.expand_tabs && self.config.tabs > 0,
+            line_diff: Default::default(),
+            timings: Default::default(),
+            row_background: Default::default(),
+            row_dimmed: Default::default(),
+            row_blanked: Default::default(),
+            line_colors: Default::default(),
+            longest_lines: Default::default(),
+            inputs_printed: Default::default(),
+            tint: Default::default(),
+            color_usage: Default::default(),
+            syntax_by_extension: Default::default(),
+        }
+    }
+
+    /// Like `build`, but fails on options that `build` would silently adjust or
+    /// that would garble the output.
+    pub fn try_build(&self) -> Result<Printer> {
+        if self.config.columns == 0 {
+            return Err(anyhow!("columns must be at least 1"));
+        }
+        if self.config.scale == 0 {
+            return Err(anyhow!("scale must be at least 1"));
+        }
+        if self.config.tab_stops.first() == Some(&0)
+            || self
+                .config
+                .tab_stops
+                .windows(2)
+                .any(|pair| pair[0] >= pair[1])
+        {
+            return Err(anyhow!("tab stops must be ascending columns after 0"));
+        }
+        if self.config.sample_rate == 0 {
+            return Err(anyhow!("sample rate must be at least 1"));
+        }
+        if self.config.vscale == 0 {
+            return Err(anyhow!("vertical scale must be at least 1"));
+        }
+        for (extension, language) in &self.config.extension_map {
+            if find_syntax_by_tokens(language).is_none() {
+                return Err(anyhow!(
+                    "unknown language {} for extension {}",
+                    language,
+                    extension
+                ));
+            }
+        }
+        if self.config.eighth_blocks && !self.config.ramp.is_empty() {
+            return Err(anyhow!("eighth blocks cannot be combined with a ramp"));
+        }
+        if let Some(glyph) = self.config.ramp.iter().find(|c| c.width() != Some(1)) {
+            return Err(anyhow!("ramp glyph {:?} is not one column wide", glyph));
+        }
+        if let Some(glyph) = self.config.control_glyph.filter(|c| c.width() != Some(1)) {
+            return Err(anyhow!("control glyph {:?} is not one column wide", glyph));
+        }
+        if !(self.config.brightness.is_finite() && self.config.brightness >= 0.0) {
+            return Err(anyhow!("brightness must be a non-negative number"));
+        }
+        if !(self.config.contrast.is_finite() && self.config.contrast >= 0.0) {
+            return Err(anyhow!("contrast must be a non-negative number"));
+        }
+        Ok(self.build())
+    }
+
+    pub fn language(&mut self, language: &str) -> &mut Self {
+        self.config.language = Some(language.to_string());
+        self
+    }
+
+    /// Uses `language` for inputs without a path, such as standard input, instead of
+    /// the one set with `language` or detecting it from the first line.
+    pub fn stdin_language(&mut self, language: &str) -> &mut Self {
+        self.config.stdin_language = Some(language.to_string());
+        self
+    }
+
+    /// Detects files with `extension` as `language` (a name, an extension, or a
+    /// comma-separated list of them like `language`), e.g. when syntect lacks a syntax
+    /// for the extension but a similar one works.
+    pub fn map_extension(&mut self, extension: &str, language: &str) -> &mut Self {
+        self.config
+            .extension_map
+            .insert(extension.to_string(), language.to_string());
+        self
+    }
+
+    /// Highlights inputs as if they continued a text from `start` on, e.g. for rendering
+    /// a fragment inside a block comment without reading the lines before it.
+    ///
+    /// `start` must be made with the syntax that inputs are highlighted with, so the
+    /// language should be set with `language`. See `highlight_start_after`.
+    pub fn highlight_start(&mut self, start: HighlightStart) -> &mut Self {
+        self.config.highlight_start = Some(start);
+        self
+    }
+
+    pub fn theme(&mut self, theme: Theme) -> &mut Self {
+        self.theme = theme;
+        self
+    }
+
+    pub fn columns(&mut self, columns: usize) -> &mut Self {
+        self.config.columns = columns;
+        self
+    }
+
+    /// Sets the tab width, `DEFAULT_TAB_WIDTH` by default. Tabs are expanded to spaces
+    /// up to the next multiple of it, and 0 passes them through like `tabs_pass_through`.
+    pub fn tabs(&mut self, tabs: usize) -> &mut Self {
+        self.config.tabs = tabs;
+        self
+    }
+
+    /// Passes tabs through instead of expanding them. They still advance to the next
+    /// multiple of `DEFAULT_TAB_WIDTH`, or to the next tab stop, when counting columns,
+    /// which may not match how wide the terminal or the editor draws them.
+    pub fn tabs_pass_through(&mut self) -> &mut Self {
+        self.tabs(0)
+    }
+
+    /// Expands tabs to the listed columns (e.g. `[4, 12, 20]`) instead of multiples of
+    /// the tab width. Past the last stop, tabs advance by the interval between the last
+    /// two stops.
+    pub fn tab_stops(&mut self, stops: &[usize]) -> &mut Self {
+        self.config.tab_stops = stops.to_vec();
+        self
+    }
+
+    pub fn expand_tabs(&mut self, yes: bool) -> &mut Self {
+        self.config.expand_tabs = yes;
+        self
+    }
+
+    pub fn color_mode(&mut self, color_mode: ColorMode) -> &mut Self {
+        self.config.color_mode = color_mode;
+        self
+    }
+
+    pub fn first_line_as_title(&mut self, yes: bool) -> &mut Self {
+        self.config.first_line_as_title = yes;
+        self
+    }
+
+    /// Overrides the foreground color of regions matching `selectors`.
+    ///
+    /// When several overrides match a region, the most specific one wins.
+    pub fn recolor(
+        &mut self,
+        selectors: ScopeSelectors,
+        color: syntect::highlighting::Color,
+    ) -> &mut Self {
+        self.config.recolor.push((selectors, color));
+        self
+    }
+
+    /// Emphasizes regions matching `selectors` by dimming all the others.
+    /// Can be called multiple times.
+    pub fn emphasize_scope(&mut self, selectors: ScopeSelectors) -> &mut Self {
+        self.config.emphasized_scopes.push(selectors);
+        self
+    }
+
+    /// Uses `color` instead of the theme's foreground color for plain text.
+    pub fn plain_color(&mut self, color: syntect::highlighting::Color) -> &mut Self {
+        self.config.plain_color = Some(color);
+        self
+    }
+
+    /// Multiplies all color components by `brightness`.
+    pub fn brightness(&mut self, brightness: f32) -> &mut Self {
+        self.config.brightness = brightness;
+        self
+    }
+
+    /// Scales the distance of all color components from the midpoint by `contrast`.
+    pub fn contrast(&mut self, contrast: f32) -> &mut Self {
+        self.config.contrast = contrast;
+        self
+    }
+
+    /// Renders only the first `left` and the last `right` columns of long lines,
+    /// with a gap marker in between. This takes the place of the maximum number of columns.
+    pub fn edges(&mut self, left: usize, right: usize) -> &mut Self {
+        self.config.edges = Some((left, right));
+        self
+    }
+
+    /// Collapses every `scale` source columns into a single cell.
+    pub fn scale(&mut self, scale: usize) -> &mut Self {
+        self.config.scale = scale;
+        self
+    }
+
+    /// Renders only the first of every `rate` lines to shrink minimaps vertically.
+    pub fn sample_rate(&mut self, rate: usize) -> &mut Self {
+        self.config.sample_rate = rate;
+        self
+    }
+
+    /// Renders only the first `lines` lines of each input, without reading the rest.
+    pub fn head(&mut self, lines: usize) -> &mut Self {
+        self.config.head = Some(lines);
+        self
+    }
+
+    /// Renders only the last `lines` lines of each input. Lines before them are not
+    /// highlighted, so the highlighting of constructs spanning lines (e.g. block
+    /// comments) starting earlier is lost.
+    pub fn tail(&mut self, lines: usize) -> &mut Self {
+        self.config.tail = Some(lines);
+        self
+    }
+
+    /// Draws every `lines` lines in a row. Two lines are drawn in the halves of cells,
+    /// and more lines in full cells of their average colors. Edges, heatmaps, wrapping,
+    /// guides, gutters, gradients, and sampling are not applied.
+    pub fn vscale(&mut self, lines: usize) -> &mut Self {
+        self.config.vscale = lines;
+        self
+    }
+
+    /// Draws cells with glyphs ordered from empty to full, chosen by how much of
+    /// the source columns behind each cell is not whitespace.
+    ///
+    /// The first glyph is used for whitespace. Glyphs should be one column wide.
+    pub fn ramp(&mut self, ramp: &str) -> &mut Self {
+        self.config.ramp = ramp.chars().collect();
+        self
+    }
+
+    /// Draws cells with left eighth blocks (`▏` to `█`) filled up to where the last
+    /// non-whitespace source column behind each cell ends, giving downscaled cells
+    /// smoother edges.
+    ///
+    /// Cells that are not downscaled are drawn as full blocks.
+    pub fn eighth_blocks(&mut self, yes: bool) -> &mut Self {
+        self.config.eighth_blocks = yes;
+        self
+    }
+
+    /// Marks the source column `column` (0-based) on every line,
+    /// e.g. 80 to see which lines exceed 80 columns.
+    pub fn guide(&mut self, column: usize) -> &mut Self {
+        self.config.guide = Some(column);
+        self
+    }
+
+    /// Adds a gutter flagging lines that exceed the guide.
+    pub fn guide_ticks(&mut self, yes: bool) -> &mut Self {
+        self.config.guide_ticks = yes;
+        self
+    }
+
+    /// Flushes the writer after every row, so that the minimap of a slow input
+    /// shows up progressively.
+    pub fn flush_lines(&mut self, yes: bool) -> &mut Self {
+        self.config.flush_lines = yes;
+        self
+    }
+
+    /// Colors code by the nesting depth of brackets instead of the theme,
+    /// cycling through `palette`.
+    pub fn rainbow_depth(&mut self, palette: &[syntect::highlighting::Color]) -> &mut Self {
+        self.config.rainbow_depth = palette.to_vec();
+        self
+    }
+
+    /// Fills the background with a vertical gradient from `top` to `bottom`,
+    /// interpolated over the rows of each input.
+    ///
+    /// The whole input is read before anything is printed to count the rows.
+    pub fn gradient(
+        &mut self,
+        top: syntect::highlighting::Color,
+        bottom: syntect::highlighting::Color,
+    ) -> &mut Self {
+        self.config.gradient = Some((top, bottom));
+        self
+    }
+
+    /// Renders lines longer than `bytes` as plain text without highlighting them,
+    /// which keeps huge lines from slowing everything down. 0 means no limit.
+    pub fn max_line_length(&mut self, bytes: usize) -> &mut Self {
+        self.config.max_line_length = bytes;
+        self
+    }
+
+    /// Collapses each line into a single cell colored with the average color of
+    /// the line weighted by width.
+    pub fn heatmap(&mut self, yes: bool) -> &mut Self {
+        self.config.heatmap = yes;
+        self
+    }
+
+    /// Continues long lines on the following rows instead of truncating them.
+    ///
+    /// Continuation rows are indented as deep as the line itself.
+    pub fn wrap(&mut self, yes: bool) -> &mut Self {
+        self.config.wrap = yes;
+        self
+    }
+
+    /// Renders only the regions selected by `filter`, leaving the rest blank.
+    pub fn scope_filter(&mut self, filter: ScopeFilter) -> &mut Self {
+        self.config.scope_filter = Some(filter);
+        self
+    }
+
+    /// Sets whether `columns` limits lines to a number of cells, the default, or of
+    /// characters, so that lines of wide characters (e.g. CJK) take more cells. Rows of
+    /// `wrap`, `edges`, and `with_source` are still measured in cells.
+    pub fn column_unit(&mut self, unit: ColumnUnit) -> &mut Self {
+        self.config.column_unit = unit;
+        self
+    }
+
+    /// Pads rows to `width` with whitespace, filled with the background set with
+    /// `whitespace_background` if any, so that minimaps are rectangular. Rows are
+    /// not padded with `rotate`, `vscale`, or `ink_profile`.
+    pub fn pad_to(&mut self, width: PadWidth) -> &mut Self {
+        self.config.pad_to = Some(width);
+        self
+    }
+
+    /// Sets what ends each row of the output.
+    pub fn row_terminator(&mut self, terminator: RowTerminator) -> &mut Self {
+        self.config.row_terminator = terminator;
+        self
+    }
+
+    /// Prints a single row instead of the minimap, where each cell is as bright as
+    /// the share of lines that are not whitespace at its column.
+    pub fn ink_profile(&mut self, yes: bool) -> &mut Self {
+        self.config.ink_profile = yes;
+        self
+    }
+
+    /// Draws control characters other than whitespace as `glyph` in a distinct color
+    /// instead of leaving them out. The glyph should be one column wide.
+    pub fn control_glyph(&mut self, glyph: char) -> &mut Self {
+        self.config.control_glyph = Some(glyph);
+        self
+    }
+
+    /// Marks line number `line` (1-based) with `color` in a gutter, followed by `label`
+    /// at the end of the row if any.
+    pub fn annotate(
+        &mut self,
+        line: usize,
+        color: syntect::highlighting::Color,
+        label: Option<&str>,
+    ) -> &mut Self {
+        self.config
+            .annotations
+            .insert(line, (color, label.map(ToString::to_string)));
+        self
+    }
+
+    /// Prints the `count` most used colors of each input after its minimap.
+    pub fn palette_summary(&mut self, count: usize) -> &mut Self {
+        self.config.palette_summary = Some(count);
+        self
+    }
+
+    /// Splits inputs other than files at lines starting with `delimiter`, rendering
+    /// each part separately with the delimiter line as its header.
+    pub fn split_on(&mut self, delimiter: &str) -> &mut Self {
+        self.config.split_on = Some(delimiter.to_string());
+        self
+    }
+
+    /// Draws faint guides at every indentation level, i.e. every tab width,
+    /// in the indentation of lines.
+    pub fn indent_guides(&mut self, yes: bool) -> &mut Self {
+        self.config.indent_guides = yes;
+        self
+    }
+
+    /// Collapses every run of whitespace after the indentation into a single blank.
+    pub fn compact_whitespace(&mut self, yes: bool) -> &mut Self {
+        self.config.compact_whitespace = yes;
+        self
+    }
+
+    /// Draws a faint mark at the start of blank lines so that they can be counted.
+    pub fn blank_marker(&mut self, yes: bool) -> &mut Self {
+        self.config.blank_marker = yes;
+        self
+    }
+
+    /// Renders lines with fewer than `columns` source columns of non-whitespace as blank
+    /// lines, e.g. to declutter lines with only a closing brace.
+    pub fn min_ink(&mut self, columns: usize) -> &mut Self {
+        self.config.min_ink = columns;
+        self
+    }
+
+    /// Fills whitespace with `color` instead of leaving the terminal's background, e.g.
+    /// when it differs from the theme's.
+    pub fn whitespace_background(&mut self, color: syntect::highlighting::Color) -> &mut Self {
+        self.config.whitespace_background = Some(color);
+        self
+    }
+
+    /// Colors text with the escape sequences in the input (e.g. captured program output)
+    /// instead of highlighting it. Escape sequences are dropped either way.
+    pub fn ansi_passthrough(&mut self, yes: bool) -> &mut Self {
+        self.config.ansi_passthrough = yes;
+        self
+    }
+
+    /// Prints control characters from inputs in titles, labels, and source text as they
+    /// are, instead of as their symbols (e.g. ␛ for escape). Escape sequences in inputs
+    /// can then reconfigure the terminal.
+    pub fn raw_control_chars(&mut self, yes: bool) -> &mut Self {
+        self.config.raw_control_chars = yes;
+        self
+    }
+
+    /// Renders lines that syntect fails to highlight as plain text instead of panicking.
+    pub fn highlight_fallback(&mut self, yes: bool) -> &mut Self {
+        self.config.highlight_fallback = yes;
+        self
+    }
+
+    /// Reverses the order of cells in rows so that minimaps read right to left, with
+    /// gutters on the right. Inputs are read to the end before they are rendered.
+    /// Edges, heatmaps, wrapping, and guides are not applied.
+    pub fn rtl(&mut self, yes: bool) -> &mut Self {
+        self.config.rtl = yes;
+        self
+    }
+
+    /// Transposes minimaps so that lines run left to right and columns top to bottom,
+    /// with two columns packed into each row. Inputs are kept in memory as cells until
+    /// their last line, so this takes memory proportional to their size. Edges, heatmaps,
+    /// wrapping, guides, gutters, and gradients are not applied.
+    pub fn rotate(&mut self, yes: bool) -> &mut Self {
+        self.config.rotate = yes;
+        self
+    }
+
+    /// Marks the lines of each input that differ from `text` in a gutter, with added and
+    /// changed lines tinted. Inputs are read to the end before they are rendered.
+    pub fn diff(&mut self, text: &str) -> &mut Self {
+        self.config.diff_base = Some(text.split_terminator('\n').map(str::to_string).collect());
+        self
+    }
+
+    /// Prints the highlighted text of each line in `width` columns to the left of
+    /// the minimap, like an editor showing both. Edges, heatmaps, wrapping, and blank
+    /// markers are not applied.
+    pub fn with_source(&mut self, width: usize) -> &mut Self {
+        self.config.with_source = Some(width);
+        self
+    }
+
+    /// Measures the time spent in each phase of printing, which is returned by
+    /// `Printer::timings`.
+    pub fn profile(&mut self, yes: bool) -> &mut Self {
+        self.config.profile = yes;
+        self
+    }
+
+    /// Tints each input printed with a hue cycling in order so that inputs are told apart,
+    /// e.g. when concatenated.
+    pub fn tint_by_file(&mut self, yes: bool) -> &mut Self {
+        self.config.tint_by_file = yes;
+        self
+    }
+
+    /// Marks the `count` longest lines of each input in a gutter. Inputs are read to
+    /// the end before they are rendered.
+    pub fn mark_longest(&mut self, count: usize) -> &mut Self {
+        self.config.mark_longest = count;
+        self
+    }
+
+    /// Emphasizes line number `line` (1-based) with a marker in a gutter and dims
+    /// the lines not emphasized. Can be called multiple times.
+    pub fn highlight_line(&mut self, line: usize) -> &mut Self {
+        self.config.highlight_lines.insert(line);
+        self
+    }
+
+    /// Keeps waiting for new lines at the end of the input like `tail -f`
+    /// instead of returning.
+    #[cfg(feature = "follow")]
+    pub fn follow(&mut self, yes: bool) -> &mut Self {
+        self.config.follow = yes;
+        self
+    }
+
+    /// Renders minimaps as images shown with the inline image `protocol` of the terminal
+    /// instead of text. Edges, heatmaps, and wrapping are not applied to images.
+    #[cfg(feature = "image")]
+    pub fn image(&mut self, protocol: ImageProtocol) -> &mut Self {
+        self.config.image = Some(protocol);
+        self
+    }
+}
+
+/// Glyph drawing each cell unless a ramp is given. It is the upper half block, so that
+/// a cell looks like a line of text with a gap below it.
+pub const CELL_GLYPH: char = '▀';
+
+// left blocks from one to eight eighths of a cell wide
+const EIGHTH_BLOCKS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+
+/// How the source maps onto terminal cells, e.g. for computing pixel sizes of
+/// exported images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderMetrics {
+    /// Terminal columns each cell occupies
+    pub cell_width: usize,
+    /// Source columns drawn in each cell
+    pub source_columns_per_cell: usize,
+    /// Source lines drawn in each row
+    pub source_lines_per_row: usize,
+}
+
+/// Time spent in phases of printing, measured if enabled with `PrinterBuilder::profile`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Detecting the languages of inputs
+    pub detection: Duration,
+    /// Highlighting lines
+    pub highlighting: Duration,
+}
+
+/// Size of a rendered minimap in terminal cells.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dimensions {
+    pub rows: usize,
+    pub columns: usize,
+}
+
+/// A run of minimap cells of the same kind and color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    /// Foreground color resolved from the theme
+    pub color: syntect::highlighting::Color,
+    /// Number of columns the run occupies
+    pub width: usize,
+    /// Whether the run comes from whitespace, which is left blank
+    pub whitespace: bool,
+    /// Fraction of the source columns behind each cell that are not whitespace,
+    /// which is below 1 only for downscaled cells
+    pub density: f32,
+    /// Fraction of each cell up to the end of the last source column behind it that is
+    /// not whitespace, which is below 1 only for downscaled cells
+    pub extent: f32,
+    /// Source text of the run
+    pub text: String,
+    /// Glyph drawn instead of the usual one, e.g. for control characters,
+    /// or drawn faintly in whitespace
+    pub glyph: Option<char>,
+    /// Background set by the theme for the scope of the run, if it differs from
+    /// the theme's background
+    pub background: Option<syntect::highlighting::Color>,
+}
+
+pub struct Printer {
+    config: RenderConfig,
+    theme: Theme,
+    // tab width, the default one for 0
+    tabs: usize,
+    tab_stops: TabStops,
+    // whether tabs are expanded, which they are not with a tab width of 0
+    expand_tabs: bool,
+    // background of the row being printed, which survives color resets
+    row_background: std::cell::Cell<Option<Color>>,
+    // whether the row being printed is dimmed to emphasize highlighted lines
+    row_dimmed: std::cell::Cell<bool>,
+    // whether the row being printed has too little ink to be drawn
+    row_blanked: std::cell::Cell<bool>,
+    // number of inputs printed so far, which picks the tint of the next one
+    inputs_printed: std::cell::Cell<usize>,
+    // hue blended into the colors of the input being printed
+    tint: std::cell::Cell<Option<syntect::highlighting::Color>>,
+    // differences of the input being printed from the text given with diff
+    line_diff: RefCell<Option<LineDiff>>,
+    // line numbers of the longest lines of the input being printed
+    longest_lines: RefCell<BTreeSet<usize>>,
+    // ends of the runs of the line being printed and their colors set by escape sequences
+    line_colors: RefCell<Vec<(usize, Option<syntect::highlighting::Color>)>>,
+    // cells drawn in each color for the palette summary
+    color_usage: RefCell<HashMap<(u8, u8, u8), usize>>,
+    // syntaxes looked up by extension so far, as batch runs see the same ones repeatedly
+    syntax_by_extension: RefCell<HashMap<OsString, Option<&'static SyntaxReference>>>,
+    // time spent in each phase so far, with profile
+    timings: std::cell::Cell<Timings>,
+}
+
+impl Printer {
+    /// Describes how the source maps onto the printed cells.
+    pub fn metrics(&self) -> RenderMetrics {
+        RenderMetrics {
+            cell_width: 1,
+            source_columns_per_cell: self.config.scale,
+            source_lines_per_row: self.config.sample_rate * self.config.vscale,
+        }
+    }
+
+    /// Returns the time spent in each phase of printing so far. All of them are zero
+    /// unless enabled with `PrinterBuilder::profile`.
+    pub fn timings(&self) -> Timings {
+        self.timings.get()
+    }
+
+    /// Sets the number of inputs printed so far, which picks the tint of the next one
+    /// with `tint_by_file`, for printers built for a single input of several.
+    pub fn set_inputs_printed(&self, count: usize) {
+        self.inputs_printed.set(count);
+    }
+
+    /// Runs `f`, adding the time it takes to the phase `phase` picks if profiling.
+    fn profiled<T, F>(&self, phase: fn(&mut Timings) -> &mut Duration, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        if !self.config.profile {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        let mut timings = self.timings.get();
+        *phase(&mut timings) += start.elapsed();
+        self.timings.set(timings);
+        result
+    }
+
+    /// Prints an input wherever it comes from. Inputs other than files are split with
+    /// `split_on` if set.
+    pub fn print_source<W: Write>(
+        &self,
+        writer: &mut W,
+        source: &InputSource,
+    ) -> Result<Dimensions> {
+        let mut reader = source.open()?;
+        match source {
+            #[cfg(feature = "notebook")]
+            InputSource::File(path) if self.is_notebook(Some(path)) => {
+                self.print_notebook(writer, &mut reader, path)
+            }
+            InputSource::File(path) => {
+                let input_reader = InputReader::new(reader)?;
+                self.print_detected(writer, input_reader, Some(path))
+            }
+            _ => self.print_from_reader_with_path(writer, &mut reader, source.path_hint()),
+        }
+    }
+
+    /// Returns the number of lines of an input and the width of the longest one in columns,
+    /// with tabs expanded as they would be rendered.
+    pub fn measure<R: BufRead>(&self, reader: R) -> io::Result<(usize, usize)> {
+        let mut lines = 0;
+        let mut width = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let line = if self.expand_tabs {
+                expand_tabs(&line, &self.tab_stops)
+            } else {
+                line
+            };
+            lines += 1;
+            width = width.max(line.width());
+        }
+        Ok((lines, width))
+    }
+
+    /// Prints from an already opened input, using `path` only to detect the language
+    /// and to name the input.
+    ///
+    /// The file at `path` is never opened.
+    pub fn print_from_reader_with_path<W, R, P>(
+        &self,
+        writer: &mut W,
+        reader: &mut R,
+        path: Option<P>,
+    ) -> Result<Dimensions>
+    where
+        W: Write,
+        R: BufRead,
+        P: AsRef<Path>,
+    {
+        if let Some(delimiter) = &self.config.split_on {
+            return self.print_split(writer, reader, delimiter);
+        }
+        #[cfg(feature = "notebook")]
+        if let Some(path) = path
+            .as_ref()
+            .filter(|path| self.is_notebook(Some(path.as_ref())))
+        {
+            return self.print_notebook(writer, reader, path.as_ref());
+        }
+
+        let input_reader = InputReader::new(reader)?;
+        self.print_detected(writer, input_reader, path.as_ref().map(AsRef::as_ref))
+    }
+
+    /// Whether `path` is a Jupyter notebook to be printed by cell, which it is not when
+    /// the language is set with `language`.
+    #[cfg(feature = "notebook")]
+    fn is_notebook(&self, path: Option<&Path>) -> bool {
+        self.config.language.is_none()
+            && path
+                .and_then(Path::extension)
+                .is_some_and(|extension| extension == "ipynb")
+    }
+
+    /// Prints each cell of a Jupyter notebook as a separate input, separated by thin
+    /// rules, instead of the JSON of the notebook.
+    #[cfg(feature = "notebook")]
+    fn print_notebook<W, R>(
+        &self,
+        writer: &mut W,
+        reader: &mut R,
+        path: &Path,
+    ) -> Result<Dimensions>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        let cells = read_notebook(reader, path)?;
+
+        let mut dims = Dimensions::default();
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                self.print_separator(writer, dims.columns)?;
+                dims.rows += 1;
+            }
+            let name = format!("{} [{}]", path.display(), i + 1);
+            let input_reader = InputReader::new(cell.source.as_bytes())?;
+            let cell_dims = self.print(writer, &name, input_reader, notebook_cell_syntax(cell))?;
+            dims.rows += cell_dims.rows;
+            dims.columns = dims.columns.max(cell_dims.columns);
+        }
+        Ok(dims)
+    }
+
+    /// Looks up the language mapped to the extension of `path` with `map_extension`.
+    fn find_mapped_syntax(&self, path: &Path) -> Option<&'static SyntaxReference> {
+        let extension = path.extension()?.to_str()?;
+        find_syntax_by_tokens(self.config.extension_map.get(extension)?)
+    }
+
+    /// Prints an input named after `path`, detecting the language from it.
+    fn print_detected<W, R>(
+        &self,
+        writer: &mut W,
+        input_reader: InputReader<R>,
+        path: Option<&Path>,
+    ) -> Result<Dimensions>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        let syntax = self.detect(path, input_reader.first_line());
+        let name = path.map_or("<stdin>".into(), Path::to_string_lossy);
+        self.print(writer, &name, input_reader, syntax)
+    }
+
+    /// Like `detect_syntax`, but also with the extensions mapped with `map_extension`.
+    fn detect(&self, path: Option<&Path>, first_line: &str) -> &'static SyntaxReference {
+        self.profiled(
+            |timings| &mut timings.detection,
+            || {
+                let mapped = path
+                    .filter(|_| self.config.language.is_none())
+                    .and_then(|path| self.find_mapped_syntax(path));
+                let language = match path {
+                    Some(_) => self.config.language.as_deref(),
+                    None => self.unnamed_language(),
+                };
+                // as in detect_syntax, but with lookups by extension cached
+                let by_path = path
+                    .filter(|_| language.is_none())
+                    .and_then(|path| self.find_syntax_by_path(path));
+                mapped
+                    .or(by_path)
+                    .unwrap_or_else(|| detect_syntax(language, None, Some(first_line)))
+            },
+        )
+    }
+
+    /// Like the function `find_syntax_by_path`, but remembering the syntaxes of
+    /// extensions.
+    fn find_syntax_by_path(&self, path: &Path) -> Option<&'static SyntaxReference> {
+        find_syntax_by_file_name(path).or_else(|| {
+            let extension = path.extension().unwrap_or_default();
+            *self
+                .syntax_by_extension
+                .borrow_mut()
+                .entry(extension.to_owned())
+                .or_insert_with(|| find_syntax_by_extension(path))
+        })
+    }
+
+    /// The language set for inputs without a path, if any.
+    fn unnamed_language(&self) -> Option<&str> {
+        self.config
+            .stdin_language
+            .as_deref()
+            .or(self.config.language.as_deref())
+    }
+
+    /// Detects the language of an input as printing it would, reading only its first line.
+    /// The first line of standard input, which cannot be read again, is kept in `source`
+    /// to be read again when printing.
+    pub fn detect_language(&self, source: &mut InputSource) -> Result<&'static str> {
+        if let InputSource::Stdin(read_ahead) = source {
+            if read_ahead.is_empty() {
+                io::stdin().lock().read_until(b'\n', read_ahead)?;
+            }
+        }
+        let first_line = InputReader::new(source.open()?)?.first_line().to_string();
+        Ok(&self.detect(source.path_hint(), &first_line).name)
+    }
+
+    /// Detects the language of an input and counts its lines without rendering it.
+    pub fn count_lines(&self, source: &InputSource) -> Result<(&'static str, usize)> {
+        let input_reader = InputReader::new(source.open()?)?;
+        let syntax = self.detect(source.path_hint(), input_reader.first_line());
+        Ok((&syntax.name, count_lines(input_reader)?))
+    }
+
+    /// Like `count_lines`, but counting the cells of Jupyter notebooks by language as
+    /// they are printed, so that a notebook may have lines in several languages.
+    pub fn count_lines_by_language(
+        &self,
+        source: &InputSource,
+    ) -> Result<Vec<(&'static str, usize)>> {
+        #[cfg(feature = "notebook")]
+        if let Some(path) = source
+            .path_hint()
+            .filter(|path| self.is_notebook(Some(path)))
+        {
+            let cells = read_notebook(&mut source.open()?, path)?;
+            let mut counts: Vec<(&str, usize)> = Vec::new();
+            for cell in &cells {
+                let language = notebook_cell_syntax(cell).name.as_str();
+                let lines = count_lines(InputReader::new(cell.source.as_bytes())?)?;
+                match counts.iter_mut().find(|(name, _)| *name == language) {
+                    Some((_, count)) => *count += lines,
+                    None => counts.push((language, lines)),
+                }
+            }
+            return Ok(counts);
+        }
+        Ok(vec![self.count_lines(source)?])
+    }
+
+    /// Prints the name of the language set with `language` and a swatch of the color of
+    /// each major kind of scope in it, without any input, and returns the number of rows.
+    ///
+    /// Scopes are resolved under the base scope of the language (e.g. `keyword` as
+    /// `keyword.rust` in `source.rust`), so rules of the theme specific to the language
+    /// apply.
+    pub fn print_legend<W: Write>(&self, writer: &mut W) -> Result<usize> {
+        let language = self
+            .config
+            .language
+            .as_deref()
+            .ok_or_else(|| anyhow!("a legend requires a language"))?;
+        let syntax = find_syntax_by_tokens(language)
+            .ok_or_else(|| anyhow!("unknown language {}", language))?;
+        let base = syntax.scope.build_string();
+        let suffix = base.rsplit('.').next().unwrap_or_default();
+
+        let highlighter = syntect::highlighting::Highlighter::new(&self.theme);
+        self.print_title(writer, &syntax.name, "")?;
+        let mut rows = 1;
+        for name in LEGEND_SCOPES {
+            let scope = Scope::new(&format!("{}.{}", name, suffix))
+                .map_err(|err| anyhow!("invalid scope {}: {:?}", name, err))?;
+            let stack = [syntax.scope, scope];
+            let color = self.recolored(&stack, highlighter.style_for_stack(&stack).foreground);
+            let color = adjust_color(color, self.config.brightness, self.config.contrast);
+            if self.config.color_mode != ColorMode::NoColor {
+                self.set_foreground(writer, &color)?;
+                crossterm::queue!(writer, style::Print("██ "))?;
+                self.reset_color(writer)?;
+            }
+            crossterm::queue!(
+                writer,
+                style::Print(format!(
+                    "#{:02x}{:02x}{:02x} {}",
+                    color.r, color.g, color.b, name
+                ))
+            )?;
+            self.terminate_row(writer)?;
+            rows += 1;
+        }
+        Ok(rows)
+    }
+
+    /// Prints each part of the input starting with a line beginning with `delimiter`
+    /// as a separate input, headed by the delimiter line.
+    fn print_split<W, R>(
+        &self,
+        writer: &mut W,
+        reader: &mut R,
+        delimiter: &str,
+    ) -> Result<Dimensions>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        let mut dims = Dimensions::default();
+        let mut header = None;
+        let mut segment = Vec::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let eof = reader.read_until(b'\n', &mut line)? == 0;
+            if eof || line.starts_with(delimiter.as_bytes()) {
+                if header.is_some() || !segment.is_empty() {
+                    let segment_dims = self.print_segment(writer, header.as_deref(), &segment)?;
+                    dims.rows += segment_dims.rows;
+                    dims.columns = dims.columns.max(segment_dims.columns);
+                }
+                if eof {
+                    return Ok(dims);
+                }
+                header = Some(String::from_utf8_lossy(&line).trim_end().to_string());
+                segment.clear();
+            } else {
+                segment.extend_from_slice(&line);
+            }
+        }
+    }
+
+    /// Prints a part of a split input, detecting the language from a file name in
+    /// `header` (e.g. "diff --git a/main.rs b/main.rs") if any.
+    fn print_segment<W: Write>(
+        &self,
+        writer: &mut W,
+        header: Option<&str>,
+        segment: &[u8],
+    ) -> Result<Dimensions> {
+        let mut dims = Dimensions::default();
+        if let Some(header) = header {
+            dims.columns = self.print_title(writer, header, "")?;
+            dims.rows += 1;
+        }
+
+        let input_reader = InputReader::new(segment)?;
+        let syntax = header
+            .filter(|_| self.config.language.is_none())
+            .and_then(|header| {
+                header.split_whitespace().rev().find_map(|token| {
+                    let path = Path::new(token);
+                    self.find_mapped_syntax(path)
+                        .or_else(|| find_syntax_by_path(path))
+                })
+            })
+            .unwrap_or_else(|| {
+                detect_syntax(
+                    self.unnamed_language(),
+                    None,
+                    Some(input_reader.first_line()),
+                )
+            });
+
+        let segment_dims = self.print(writer, header.unwrap_or("<stdin>"), input_reader, syntax)?;
+        dims.rows += segment_dims.rows;
+        dims.columns = dims.columns.max(segment_dims.columns);
+        Ok(dims)
+    }
+
+    /// Prints a thin rule of `width` columns, capped at the maximum number of columns.
+    pub fn print_separator<W: Write>(&self, writer: &mut W, width: usize) -> Result<()> {
+        self.set_attribute(writer, Attribute::Dim)?;
+        crossterm::queue!(
+            writer,
+            style::Print("─".repeat(width.min(self.config.columns)))
+        )?;
+        self.set_attribute(writer, Attribute::Reset)?;
+        self.terminate_row(writer)?;
+        Ok(())
+    }
+
+    /// Prints `heading` as a row in bold, e.g. above a group of inputs.
+    pub fn print_heading<W: Write>(&self, writer: &mut W, heading: &str) -> Result<usize> {
+        self.print_title(writer, heading, "")
+    }
+
+    /// Prints an empty row.
+    pub fn print_blank_row<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.terminate_row(writer)
+    }
+
+    fn print<W, R>(
+        &self,
+        writer: &mut W,
+        name: &str,
+        mut input_reader: InputReader<R>,
+        syntax: &SyntaxReference,
+    ) -> Result<Dimensions>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        let mut highlighter = match &self.config.highlight_start {
+            Some(start) => LineHighlighter::with_start(syntax, &self.theme, start.clone()),
+            None => LineHighlighter::new(syntax, &self.theme),
+        };
+
+        if self.config.tint_by_file {
+            let index = self.inputs_printed.get();
+            self.tint
+                .set(Some(TINT_PALETTE[index % TINT_PALETTE.len()]));
+        }
+        self.inputs_printed.set(self.inputs_printed.get() + 1);
+
+        let mut dims = Dimensions::default();
+
+        if self.config.first_line_as_title {
+            let width = self.print_title(writer, name, input_reader.first_line())?;
+            dims.rows += 1;
+            dims.columns = width;
+        }
+
+        if let Some(lines) = self.config.head {
+            input_reader.limit(lines);
+        }
+        if let Some(lines) = self.config.tail {
+            input_reader.skip_to_last(lines)?;
+        }
+
+        #[cfg(feature = "image")]
+        if let Some(protocol) = self.config.image {
+            let image_dims =
+                self.print_image(writer, &mut input_reader, &mut highlighter, protocol)?;
+            dims.rows += image_dims.rows;
+            dims.columns = dims.columns.max(image_dims.columns);
+            return Ok(dims);
+        }
+
+        if self.config.ink_profile {
+            let width = self.print_ink_profile(writer, &mut input_reader)?;
+            dims.rows += 1;
+            dims.columns = dims.columns.max(width);
+            return Ok(dims);
+        }
+
+        if self.config.vscale > 1 {
+            self.print_vscaled(writer, &mut input_reader, &mut highlighter, &mut dims)?;
+            if let Some(count) = self.config.palette_summary {
+                dims.rows += self.print_palette_summary(writer, count)?;
+            }
+            return Ok(dims);
+        }
+
+        if self.config.rotate {
+            let rotated_dims = self.print_rotated(writer, &mut input_reader, &mut highlighter)?;
+            dims.rows += rotated_dims.rows;
+            dims.columns = dims.columns.max(rotated_dims.columns);
+            if let Some(count) = self.config.palette_summary {
+                dims.rows += self.print_palette_summary(writer, count)?;
+            }
+            return Ok(dims);
+        }
+
+        // the longest lines can only be told after reading all of them
+        if self.config.mark_longest > 0 {
+            let lines = input_reader.buffer()?;
+            let mut widths: Vec<_> = lines
+                .iter()
+                .enumerate()
+                .map(|(i, line)| (expand_tabs(line, &self.tab_stops).width(), i + 1))
+                .filter(|(width, _)| *width > 0)
+                .collect();
+            widths.sort_by(|(a_width, a_line), (b_width, b_line)| {
+                b_width.cmp(a_width).then(a_line.cmp(b_line))
+            });
+            *self.longest_lines.borrow_mut() = widths
+                .into_iter()
+                .take(self.config.mark_longest)
+                .map(|(_, line)| line)
+                .collect();
+        }
+
+        if let Some(base) = &self.config.diff_base {
+            let base: Vec<_> = base.iter().map(String::as_str).collect();
+            let lines: Vec<_> = input_reader.buffer()?.iter().map(String::as_str).collect();
+            *self.line_diff.borrow_mut() = Some(LineDiff::new(&base, &lines));
+        }
+
+        // rows are aligned to the right end of the widest one
+        let rtl_width = if self.config.rtl {
+            let lines = input_reader.buffer()?;
+            let widest = lines
+                .iter()
+                .map(|line| {
+                    expand_tabs(line, &self.tab_stops)
+                        .width()
+                        .div_ceil(self.config.scale)
+                })
+                .max()
+                .unwrap_or(0);
+            Some(widest.min(self.config.columns))
+        } else {
+            None
+        };
+
+        // the gradient spans all the rows, so they are counted up front
+        let gradient = match self.config.gradient {
+            Some(gradient) if self.config.color_mode != ColorMode::NoColor => {
+                let lines = input_reader.buffer()?;
+                Some((gradient, lines.len(), self.fill_width(lines)))
+            }
+            _ => None,
+        };
+
+        let pad_width = match self.config.pad_to {
+            Some(PadWidth::Columns(columns)) => Some(columns),
+            Some(PadWidth::Widest) => Some(self.fill_width(input_reader.buffer()?)),
+            None => None,
+        };
+
+        let mut buf = String::new();
+        let mut row = 0;
+        let mut sgr_color = None;
+        while self.read_line(writer, &mut input_reader, &mut buf)? {
+            let mut line = self.prepare_line(&mut buf, &mut sgr_color);
+
+            // some syntaxes (e.g. embedded languages) rely on the newline to end contexts
+            line.push('\n');
+
+            let background = gradient.and_then(|((top, bottom), rows, _)| {
+                let t = row as f32 / rows.saturating_sub(1).max(1) as f32;
+                convert_color(&lerp_color(top, bottom, t), self.config.color_mode)
+            });
+            let fill_width = gradient.map(|(_, _, fill_width)| fill_width).max(pad_width);
+            row += 1;
+            if (row - 1) % self.config.sample_rate != 0 {
+                // keeps the highlighting state in sync with the lines that follow
+                self.highlight_line_to_cells(&line, &mut highlighter);
+                continue;
+            }
+            self.row_dimmed.set(
+                !self.config.highlight_lines.is_empty()
+                    && !self.config.highlight_lines.contains(&row),
+            );
+
+            let background = background.or_else(|| self.diff_background(row));
+            self.row_background.set(background);
+            self.restore_row_background(writer)?;
+            let sparse = ink_width(&line) < self.config.min_ink;
+            self.row_blanked.set(sparse);
+            let mut width = 0;
+            let continuation_rows = if let Some(source_width) = self.config.with_source {
+                // the line is highlighted once for both the text and the minimap
+                let mut cells = self.layout_line(&line, &mut highlighter, usize::MAX);
+                width += self.print_source_text(writer, &cells, source_width)?;
+                width += self.print_gutter(writer, Some((&line, row)))?;
+                split_cells_off(
+                    &mut cells,
+                    self.config.columns.saturating_mul(self.config.scale),
+                );
+                let cells = self.add_indent_guides(downscale(&cells, self.config.scale));
+                width += self.print_cells(writer, cells, self.guide_column())?;
+                Vec::new()
+            } else if let Some(rtl_width) = rtl_width {
+                width += self.print_rtl_line(writer, &line, &mut highlighter, rtl_width)?;
+                width += self.print_gutter(writer, Some((&line, row)))?;
+                Vec::new()
+            } else if self.config.blank_marker && (sparse || line.trim().is_empty()) {
+                width += self.print_gutter(writer, Some((&line, row)))?;
+                // keeps the parse state in sync with the lines that follow
+                self.highlight_regions(&line, &mut highlighter);
+                self.reset_color(writer)?;
+                self.set_attribute(writer, Attribute::Dim)?;
+                crossterm::queue!(writer, style::Print(BLANK_MARKER))?;
+                self.set_attribute(writer, Attribute::Reset)?;
+                width += 1;
+                Vec::new()
+            } else if self.config.wrap {
+                width += self.print_gutter(writer, Some((&line, row)))?;
+                let mut rows = self.wrap_line(&line, &mut highlighter).into_iter();
+                let cells = rows.next().unwrap_or_default();
+                width += self.print_cells(writer, cells, self.guide_column())?;
+                rows.collect()
+            } else {
+                width += self.print_gutter(writer, Some((&line, row)))?;
+                width += self.print_line(writer, &line, &mut highlighter)?;
+                Vec::new()
+            };
+            if let Some((_, Some(label))) = self.config.annotations.get(&row) {
+                // measured as printed, with control characters as their pictures
+                let label = self.sanitize(label);
+                self.reset_color(writer)?;
+                self.set_attribute(writer, Attribute::Dim)?;
+                crossterm::queue!(writer, style::Print(' '), style::Print(&label))?;
+                self.set_attribute(writer, Attribute::Reset)?;
+                width += 1 + label.width();
+            }
+            self.end_row(writer, width, fill_width, &mut dims)?;
+
+            for cells in continuation_rows {
+                self.row_background.set(background);
+                self.restore_row_background(writer)?;
+                let mut width = self.print_gutter(writer, None)?;
+                width += self.print_cells(writer, cells, None)?;
+                self.end_row(writer, width, fill_width, &mut dims)?;
+            }
+        }
+        self.row_dimmed.set(false);
+        self.row_blanked.set(false);
+
+        if let Some(count) = self.config.palette_summary {
+            dims.rows += self.print_palette_summary(writer, count)?;
+        }
+
+        Ok(dims)
+    }
+
+    /// Prints swatches of the `count` most used colors since the last summary with
+    /// their shares of the cells that are not whitespace, and returns the number of rows.
+    fn print_palette_summary<W: Write>(&self, writer: &mut W, count: usize) -> Result<usize> {
+        let usage = std::mem::take(&mut *self.color_usage.borrow_mut());
+        let total: usize = usage.values().sum();
+        let mut usage: Vec<_> = usage.into_iter().collect();
+        usage.sort_by(|(a_color, a), (b_color, b)| b.cmp(a).then(a_color.cmp(b_color)));
+
+        let mut rows = 0;
+        for ((r, g, b), cells) in usage.into_iter().take(count) {
+            let color = syntect::highlighting::Color { r, g, b, a: 0xff };
+            if self.config.color_mode != ColorMode::NoColor {
+                self.set_foreground(writer, &color)?;
+                crossterm::queue!(writer, style::Print("██ "))?;
+                self.reset_color(writer)?;
+            }
+            let share = cells as f32 / total as f32 * 100.0;
+            crossterm::queue!(
+                writer,
+                style::Print(format!("#{:02x}{:02x}{:02x} {:5.1}%", r, g, b, share))
+            )?;
+            self.terminate_row(writer)?;
+            rows += 1;
+        }
+        Ok(rows)
+    }
+
+    /// Prints the gutter for line number `line` (1-based), or for a continuation row.
+    fn print_gutter<W: Write>(&self, writer: &mut W, line: Option<(&str, usize)>) -> Result<usize> {
+        let mut width = 0;
+        if let (Some(guide), true) = (self.config.guide, self.config.guide_ticks) {
+            match line {
+                Some((line, _)) if line.trim_end().width() > guide => {
+                    self.set_foreground(writer, &TICK_COLOR)?;
+                    crossterm::queue!(writer, style::Print('▌'))?;
+                    self.reset_color(writer)?;
+                }
+                _ => crossterm::queue!(writer, style::Print(' '))?,
+            }
+            width += 1;
+        }
+        if self.config.mark_longest > 0 {
+            match line {
+                Some((_, line)) if self.longest_lines.borrow().contains(&line) => {
+                    self.set_foreground(writer, &TICK_COLOR)?;
+                    crossterm::queue!(writer, style::Print('◆'))?;
+                    self.reset_color(writer)?;
+                }
+                _ => crossterm::queue!(writer, style::Print(' '))?,
+            }
+            width += 1;
+        }
+        if !self.config.highlight_lines.is_empty() {
+            match line {
+                Some((_, line)) if self.config.highlight_lines.contains(&line) => {
+                    crossterm::queue!(writer, style::Print('▶'))?
+                }
+                _ => crossterm::queue!(writer, style::Print(' '))?,
+            }
+            width += 1;
+        }
+        if !self.config.annotations.is_empty() {
+            match line.and_then(|(_, line)| self.config.annotations.get(&line)) {
+                Some((color, _)) => {
+                    self.set_foreground(writer, color)?;
+                    crossterm::queue!(writer, style::Print('▌'))?;
+                    self.reset_color(writer)?;
+                }
+                None => crossterm::queue!(writer, style::Print(' '))?,
+            }
+            width += 1;
+        }
+        if self.config.diff_base.is_some() {
+            let change = line.and_then(|(_, line)| self.line_change(line));
+            let mark = change.map(|change| match change {
+                LineChange::Added => (DIFF_ADDED_COLOR, '▌'),
+                LineChange::Changed => (DIFF_CHANGED_COLOR, '▌'),
+                LineChange::RemovedAbove => (DIFF_REMOVED_COLOR, '▔'),
+                LineChange::RemovedBelow => (DIFF_REMOVED_COLOR, '▁'),
+            });
+            match mark {
+                Some((color, glyph)) => {
+                    self.set_foreground(writer, &color)?;
+                    crossterm::queue!(writer, style::Print(glyph))?;
+                    self.reset_color(writer)?;
+                }
+                None => crossterm::queue!(writer, style::Print(' '))?,
+            }
+            width += 1;
+        }
+        Ok(width)
+    }
+
+    /// Returns how line number `line` (1-based) differs from the text given with `diff`.
+    fn line_change(&self, line: usize) -> Option<LineChange> {
+        self.line_diff.borrow().as_ref()?.change(line - 1)
+    }
+
+    /// Returns the background tinting line number `line` (1-based) if it is added or
+    /// changed from the text given with `diff`.
+    fn diff_background(&self, line: usize) -> Option<Color> {
+        let background = match self.line_change(line)? {
+            LineChange::Added => DIFF_ADDED_BACKGROUND,
+            LineChange::Changed => DIFF_CHANGED_BACKGROUND,
+            LineChange::RemovedAbove | LineChange::RemovedBelow => return None,
+        };
+        convert_color(&background, self.config.color_mode)
+    }
+
+    /// Prints the input as an image, returning the dimensions it would have as text.
+    #[cfg(feature = "image")]
+    fn print_image<W, R>(
+        &self,
+        writer: &mut W,
+        input_reader: &mut InputReader<R>,
+        highlighter: &mut LineHighlighter,
+        protocol: ImageProtocol,
+    ) -> Result<Dimensions>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        let mut rows = Vec::new();
+        let mut buf = String::new();
+        let mut sgr_color = None;
+        while input_reader.read_line(&mut buf)? {
+            let mut line = self.prepare_line(&mut buf, &mut sgr_color);
+            line.push('\n');
+            rows.push(self.highlight_line_to_cells(&line, highlighter));
+        }
+
+        let columns = rows
+            .iter()
+            .map(|cells| cells.iter().map(|cell| cell.width).sum())
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return Ok(Dimensions {
+                rows: rows.len(),
+                columns,
+            });
+        }
+
+        let mut raster = Raster::new(columns * IMAGE_CELL_WIDTH, rows.len() * IMAGE_ROW_HEIGHT);
+        for (y, cells) in rows.iter().enumerate() {
+            let mut x = 0;
+            for cell in cells {
+                if !cell.whitespace {
+                    let alpha = (cell.density.clamp(0.0, 1.0) * 255.0).round() as u8;
+                    raster.fill(
+                        x * IMAGE_CELL_WIDTH,
+                        y * IMAGE_ROW_HEIGHT,
+                        cell.width * IMAGE_CELL_WIDTH,
+                        IMAGE_ROW_HEIGHT / 2,
+                        [cell.color.r, cell.color.g, cell.color.b, alpha],
+                    );
+                }
+                x += cell.width;
+            }
+        }
+        image::write_image(writer, &raster, protocol)?;
+
+        Ok(Dimensions {
+            rows: rows.len(),
+            columns,
+        })
+    }
+
+    /// Prints the input transposed, with a column for each line and a row for each
+    /// two columns drawn as the upper and lower halves of cells.
+    fn print_rotated<W, R>(
+        &self,
+        writer: &mut W,
+        input_reader: &mut InputReader<R>,
+        highlighter: &mut LineHighlighter,
+    ) -> Result<Dimensions>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        // the color of each column of each line, None for whitespace
+        let mut lines: Vec<Vec<Option<syntect::highlighting::Color>>> = Vec::new();
+        let mut buf = String::new();
+        let mut sgr_color = None;
+        let mut row = 0;
+        while input_reader.read_line(&mut buf)? {
+            let mut line = self.prepare_line(&mut buf, &mut sgr_color);
+            line.push('\n');
+            let cells = self.highlight_line_to_cells(&line, highlighter);
+            row += 1;
+            if (row - 1) % self.config.sample_rate != 0 {
+                continue;
+            }
+            lines.push(column_colors(&cells));
+        }
+
+        let height = lines.iter().map(Vec::len).max().unwrap_or(0);
+        let mut dims = Dimensions::default();
+        for top in (0..height).step_by(2) {
+            for columns in &lines {
+                let upper = columns.get(top).copied().flatten();
+                let lower = columns.get(top + 1).copied().flatten();
+                self.print_half_block(writer, upper, lower)?;
+            }
+            self.end_row(writer, lines.len(), None, &mut dims)?;
+        }
+        Ok(dims)
+    }
+
+    /// Prints every `vscale` lines as a row, with two lines in the halves of each cell or
+    /// more lines averaged into full cells.
+    fn print_vscaled<W, R>(
+        &self,
+        writer: &mut W,
+        input_reader: &mut InputReader<R>,
+        highlighter: &mut LineHighlighter,
+        dims: &mut Dimensions,
+    ) -> Result<()>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        let mut buf = String::new();
+        let mut sgr_color = None;
+        // colors of the columns of the lines making the next row
+        let mut lines = Vec::with_capacity(self.config.vscale);
+        loop {
+            let more = self.read_line(writer, input_reader, &mut buf)?;
+            if more {
+                let mut line = self.prepare_line(&mut buf, &mut sgr_color);
+                line.push('\n');
+                lines.push(column_colors(
+                    &self.highlight_line_to_cells(&line, highlighter),
+                ));
+                if lines.len() < self.config.vscale {
+                    continue;
+                }
+            }
+            if !lines.is_empty() {
+                let width = lines.iter().map(Vec::len).max().unwrap_or(0);
+                for column in 0..width {
+                    let mut colors = lines
+                        .iter()
+                        .map(|columns: &Vec<_>| columns.get(column).copied().flatten());
+                    if self.config.vscale == 2 {
+                        let upper = colors.next().flatten();
+                        let lower = colors.next().flatten();
+                        self.print_half_block(writer, upper, lower)?;
+                        continue;
+                    }
+
+                    self.reset_color(writer)?;
+                    self.set_whitespace_background(writer)?;
+                    let inked: Vec<_> = colors.flatten().collect();
+                    if inked.is_empty() {
+                        crossterm::queue!(writer, style::Print(' '))?;
+                        continue;
+                    }
+                    let average = |channel: fn(&syntect::highlighting::Color) -> u8| {
+                        let sum: u32 = inked.iter().map(|color| channel(color) as u32).sum();
+                        (sum as f32 / inked.len() as f32).round() as u8
+                    };
+                    let color = syntect::highlighting::Color {
+                        r: average(|color| color.r),
+                        g: average(|color| color.g),
+                        b: average(|color| color.b),
+                        a: 0xff,
+                    };
+                    self.count_color_usage(&color);
+                    self.set_foreground(writer, &color)?;
+                    crossterm::queue!(writer, style::Print('█'))?;
+                }
+                self.end_row(writer, width, None, dims)?;
+                lines.clear();
+            }
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Prints a cell showing `upper` and `lower` in its halves, where `None` is blank.
+    fn print_half_block<W: Write>(
+        &self,
+        writer: &mut W,
+        upper: Option<syntect::highlighting::Color>,
+        lower: Option<syntect::highlighting::Color>,
+    ) -> Result<()> {
+        self.reset_color(writer)?;
+        self.set_whitespace_background(writer)?;
+        for color in upper.iter().chain(lower.iter()) {
+            self.count_color_usage(color);
+        }
+        let glyph = match (upper, lower) {
+            (Some(upper), Some(lower)) => {
+                self.set_foreground(writer, &upper)?;
+                match convert_color(&lower, self.config.color_mode) {
+                    Some(background) => {
+                        crossterm::queue!(writer, style::SetBackgroundColor(background))?;
+                        '▀'
+                    }
+                    None => '█',
+                }
+            }
+            (Some(upper), None) => {
+                self.set_foreground(writer, &upper)?;
+                '▀'
+            }
+            (None, Some(lower)) => {
+                self.set_foreground(writer, &lower)?;
+                '▄'
+            }
+            (None, None) => ' ',
+        };
+        crossterm::queue!(writer, style::Print(glyph))?;
+        Ok(())
+    }
+
+    /// Counts a cell drawn in `color` for the palette summary.
+    fn count_color_usage(&self, color: &syntect::highlighting::Color) {
+        if self.config.palette_summary.is_some() {
+            let color = (color.r, color.g, color.b);
+            *self.color_usage.borrow_mut().entry(color).or_insert(0) += 1;
+        }
+    }
+
+    fn print_ink_profile<W, R>(
+        &self,
+        writer: &mut W,
+        input_reader: &mut InputReader<R>,
+    ) -> Result<usize>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        // number of lines with ink at each column
+        let mut counts: Vec<usize> = Vec::new();
+        let mut lines = 0;
+        let mut buf = String::new();
+        while input_reader.read_line(&mut buf)? {
+            let line = expand_tabs(&buf, &self.tab_stops);
+            buf.clear();
+            lines += 1;
+
+            let mut column = 0;
+            let mut last_counted = None;
+            for c in line.chars() {
+                let width = c.width().unwrap_or(0);
+                if !c.is_whitespace() {
+                    for cell in (column..column + width).map(|column| column / self.config.scale) {
+                        if cell >= self.config.columns {
+                            break;
+                        }
+                        if last_counted == Some(cell) {
+                            continue;
+                        }
+                        if counts.len() <= cell {
+                            counts.resize(cell + 1, 0);
+                        }
+                        counts[cell] += 1;
+                        last_counted = Some(cell);
+                    }
+                }
+                column += width;
+            }
+        }
+
+        let foreground = self
+            .config
+            .plain_color
+            .or(self.theme.settings.foreground)
+            .unwrap_or(syntect::highlighting::Color::WHITE);
+        let background = self
+            .theme
+            .settings
+            .background
+            .unwrap_or(syntect::highlighting::Color::BLACK);
+        let cells = counts
+            .into_iter()
+            .map(|count| {
+                let share = count as f32 / lines as f32;
+                Cell {
+                    color: lerp_color(background, foreground, share),
+                    width: 1,
+                    whitespace: count == 0,
+                    density: share,
+                    extent: 1.0,
+                    text: " ".to_string(),
+                    glyph: None,
+                    background: None,
+                }
+            })
+            .collect();
+
+        let width = self.print_cells(writer, cells, None)?;
+        self.reset_color(writer)?;
+        self.terminate_row(writer)?;
+        Ok(width)
+    }
+
+    /// Finishes a row of `width` columns, filling the background up to `fill_width`.
+    fn end_row<W: Write>(
+        &self,
+        writer: &mut W,
+        mut width: usize,
+        fill_width: Option<usize>,
+        dims: &mut Dimensions,
+    ) -> Result<()> {
+        if let Some(fill_width) = fill_width {
+            if width < fill_width {
+                self.reset_color(writer)?;
+                self.set_whitespace_background(writer)?;
+                crossterm::queue!(writer, style::Print(" ".repeat(fill_width - width)))?;
+                width = fill_width;
+            }
+        }
+        self.row_background.set(None);
+        dims.rows += 1;
+        dims.columns = dims.columns.max(width);
+
+        self.reset_color(writer)?;
+        self.terminate_row(writer)?;
+        if self.config.flush_lines {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Estimates the number of columns of the widest row of `lines`, so that
+    /// backgrounds can be filled up to it.
+    fn fill_width<'a, I>(&self, lines: I) -> usize
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let widest = lines
+            .into_iter()
+            .map(|line| expand_tabs(line, &self.tab_stops).width())
+            .max()
+            .unwrap_or(0);
+        let widest = widest.div_ceil(self.metrics().source_columns_per_cell);
+        let mut width = match self.config.edges {
+            Some((left, right)) if widest > left + right => left + 1 + right,
+            Some(_) => widest,
+            None => widest.min(self.config.columns),
+        };
+        if let Some(guide) = self.guide_column() {
+            width = width.max(guide + 1);
+        }
+        if self.config.guide.is_some() && self.config.guide_ticks {
+            width += 1;
+        }
+        if self.config.mark_longest > 0 {
+            width += 1;
+        }
+        if !self.config.highlight_lines.is_empty() {
+            width += 1;
+        }
+        if !self.config.annotations.is_empty() {
+            width += 1;
+        }
+        if self.config.diff_base.is_some() {
+            width += 1;
+        }
+        if let Some(source_width) = self.config.with_source {
+            width += source_width + 1;
+        }
+        width
+    }
+
+    /// Takes the line read into `buf`, dropping escape sequences and expanding tabs.
+    ///
+    /// With `ansi_passthrough`, the colors set by the sequences are kept for `layout_line`,
+    /// carrying the color in effect over lines in `sgr_color`.
+    fn prepare_line(
+        &self,
+        buf: &mut String,
+        sgr_color: &mut Option<syntect::highlighting::Color>,
+    ) -> String {
+        if !self.config.ansi_passthrough && !buf.contains('\x1b') {
+            return if self.expand_tabs {
+                let expanded = expand_tabs(buf, &self.tab_stops);
+                buf.clear();
+                expanded
+            } else {
+                std::mem::take(buf)
+            };
+        }
+
+        let mut line = String::with_capacity(buf.len());
+        let mut ends = Vec::new();
+        let mut column = 0;
+        for (text, color) in ansi::parse(buf, sgr_color) {
+            if self.expand_tabs {
+                line.push_str(&expand_tabs_from(&text, &self.tab_stops, &mut column));
+            } else {
+                line.push_str(&text);
+            }
+            ends.push((line.len(), color));
+        }
+        buf.clear();
+
+        if self.config.ansi_passthrough {
+            *self.line_colors.borrow_mut() = ends;
+        }
+        line
+    }
+
+    /// Reads the next line, polling for a complete one when following the input.
+    fn read_line<W, R>(
+        &self,
+        writer: &mut W,
+        input_reader: &mut InputReader<R>,
+        buf: &mut String,
+    ) -> Result<bool>
+    where
+        W: Write,
+        R: BufRead,
+    {
+        #[cfg(feature = "follow")]
+        if self.config.follow {
+            while !input_reader.read_complete_line(buf)? {
+                writer.flush()?;
+                std::thread::sleep(FOLLOW_INTERVAL);
+            }
+            return Ok(true);
+        }
+
+        let _ = writer;
+        Ok(input_reader.read_line(buf)?)
+    }
+
+    fn print_title<W: Write>(&self, writer: &mut W, name: &str, first_line: &str) -> Result<usize> {
+        let name = self.sanitize(name);
+        let first_line = self.sanitize(first_line);
+        let name = truncate_to_width(&name, self.config.columns);
+        self.set_attribute(writer, Attribute::Bold)?;
+        crossterm::queue!(writer, style::Print(name))?;
+        self.set_attribute(writer, Attribute::Reset)?;
+
+        let subtitle = expand_tabs(first_line.trim(), &self.tab_stops);
+        let mut width = name.width();
+        let remaining = self.config.columns.saturating_sub(width + 1);
+        if !subtitle.is_empty() && remaining > 0 {
+            let subtitle = truncate_to_width(&subtitle, remaining);
+            crossterm::queue!(writer, style::Print(' '))?;
+            self.set_attribute(writer, Attribute::Dim)?;
+            crossterm::queue!(writer, style::Print(subtitle))?;
+            self.set_attribute(writer, Attribute::Reset)?;
+            width += 1 + subtitle.width();
+        }
+
+        self.terminate_row(writer)?;
+        Ok(width)
+    }
+
+    /// Highlights a line and lays it out as minimap cells, truncated to the maximum
+    /// number of columns.
+    ///
+    /// `line` should end with a newline. This advances the internal state of `highlighter`,
+    /// so lines have to be passed in order.
+    pub fn highlight_line_to_cells(
+        &self,
+        line: &str,
+        highlighter: &mut LineHighlighter,
+    ) -> Vec<Cell> {
+        let columns = self.config.columns.saturating_mul(self.config.scale);
+        let columns = match self.config.column_unit {
+            ColumnUnit::Cells => columns,
+            // as many cells as the characters up to the limit take
+            ColumnUnit::Chars => match line.char_indices().nth(columns) {
+                Some((end, _)) => line[..end].width(),
+                None => usize::MAX,
+            },
+        };
+        let cells = self.layout_line(line, highlighter, columns);
+        downscale(&cells, self.config.scale)
+    }
+
+    /// Highlights a line. With `highlight_fallback`, returns `None` instead of panicking
+    /// if syntect panics or yields regions not covering the line.
+    fn highlight_regions<'a>(
+        &self,
+        line: &'a str,
+        highlighter: &mut LineHighlighter,
+    ) -> Option<Vec<Region<'a>>> {
+        let mut highlight = || {
+            self.profiled(
+                |timings| &mut timings.highlighting,
+                || highlighter.highlight(line, &SYNTAX_SET),
+            )
+        };
+        if !self.config.highlight_fallback {
+            return Some(highlight());
+        }
+
+        let regions = std::panic::catch_unwind(std::panic::AssertUnwindSafe(highlight)).ok()?;
+        let covered: usize = regions.iter().map(|region| region.text.len()).sum();
+        if covered == line.len() {
+            Some(regions)
+        } else {
+            None
+        }
+    }
+
+    /// Splits a line prepared by `prepare_line` into regions of the colors set by
+    /// escape sequences, using the plain text color where none is set.
+    fn passthrough_regions<'a>(&self, line: &'a str) -> Vec<Region<'a>> {
+        let default_color = self
+            .config
+            .plain_color
+            .or(self.theme.settings.foreground)
+            .unwrap_or(syntect::highlighting::Color::WHITE);
+        let region = |text, color: Option<_>| Region {
+            style: Style {
+                foreground: color.unwrap_or(default_color),
+                ..Style::default()
+            },
+            text,
+            scopes: ScopeStack::new(),
+        };
+
+        let mut regions = Vec::new();
+        let mut start = 0;
+        for &(end, color) in self.line_colors.borrow().iter() {
+            let end = end.min(line.len());
+            if end > start {
+                regions.push(region(&line[start..end], color));
+                start = end;
+            }
+        }
+        if start < line.len() {
+            regions.push(region(&line[start..], None));
+        }
+        regions
+    }
+
+    fn layout_line(
+        &self,
+        line: &str,
+        highlighter: &mut LineHighlighter,
+        columns: usize,
+    ) -> Vec<Cell> {
+        // highlighting gets slow on huge lines (e.g. minified code), so they are
+        // rendered as plain text instead
+        let too_long = self.config.max_line_length > 0 && line.len() > self.config.max_line_length;
+        let regions = if too_long {
+            None
+        } else if self.config.ansi_passthrough {
+            Some(self.passthrough_regions(line))
+        } else {
+            self.highlight_regions(line, highlighter)
+        };
+        let plain_text =
+            !self.config.ansi_passthrough && (regions.is_none() || highlighter.is_plain_text());
+        let regions = regions.unwrap_or_else(|| {
+            let style = Style {
+                foreground: self
+                    .theme
+                    .settings
+                    .foreground
+                    .unwrap_or(syntect::highlighting::Color::WHITE),
+                ..Style::default()
+            };
+            vec![Region {
+                style,
+                text: line,
+                scopes: ScopeStack::new(),
+            }]
+        });
+
+        // depths are tracked over whole lines even if they get truncated
+        let depths: Vec<_> = if self.config.rainbow_depth.is_empty() {
+            Vec::new()
+        } else {
+            regions
+                .iter()
+                .map(|region| highlighter.bracket_depths(region))
+                .collect()
+        };
+
+        let mut cells: Vec<Cell> = Vec::new();
+        let mut printed_columns = 0;
+        // whether only indentation has been laid out so far
+        let mut leading = true;
+        for (i, region) in regions.into_iter().enumerate() {
+            let color = match self.config.plain_color {
+                Some(color) if plain_text => color,
+                _ => self.scope_color(&region),
+            };
+            // plain text and escape sequences carry no backgrounds of scopes
+            let background = Some(region.style.background).filter(|background| {
+                !plain_text
+                    && !self.config.ansi_passthrough
+                    && background.a > 0
+                    && Some(*background) != self.theme.settings.background
+            });
+
+            let text = region.text.trim_end_matches('\n');
+            let blank;
+            let (text, depths) = if self.is_hidden(&region) {
+                blank = blank_out(text);
+                (blank.as_str(), None)
+            } else {
+                (text, depths.get(i))
+            };
+            let chars = text
+                .chars()
+                .enumerate()
+                .map(|(j, c)| (c, depths.map_or(0, |depths| depths[j])));
+            let control = |c: char| {
+                self.config.control_glyph.is_some() && c.is_control() && !c.is_whitespace()
+            };
+            for ((whitespace, control, depth), group) in
+                &chars.group_by(|(c, depth)| (c.is_whitespace(), control(*c), *depth))
+            {
+                let group = group.map(|(c, _)| c);
+                if let (true, Some(glyph)) = (control, self.config.control_glyph) {
+                    leading = false;
+                    let count = group.count();
+                    let width = count.min(columns - printed_columns);
+                    printed_columns += width;
+                    cells.push(Cell {
+                        color: adjust_color(
+                            CONTROL_COLOR,
+                            self.config.brightness,
+                            self.config.contrast,
+                        ),
+                        width,
+                        whitespace: false,
+                        density: 1.0,
+                        extent: 1.0,
+                        text: glyph.to_string().repeat(width),
+                        glyph: Some(glyph),
+                        background,
+                    });
+                    if width < count || printed_columns >= columns {
+                        return cells;
+                    }
+                    continue;
+                }
+
+                let color = if self.config.rainbow_depth.is_empty() {
+                    color
+                } else {
+                    self.config.rainbow_depth[depth % self.config.rainbow_depth.len()]
+                };
+                let color = adjust_color(color, self.config.brightness, self.config.contrast);
+                let color = if self.is_deemphasized(&region) {
+                    adjust_color(color, DIMMED_BRIGHTNESS, 1.0)
+                } else {
+                    color
+                };
+                let color = match self.tint.get() {
+                    Some(tint) => lerp_color(color, tint, TINT_STRENGTH),
+                    None => color,
+                };
+                let mut truncated = false;
+
+                if !whitespace {
+                    leading = false;
+                }
+
+                let (text, width) = if whitespace && self.config.compact_whitespace && !leading {
+                    // the run may continue from the previous region
+                    if cells.last().is_some_and(|cell| cell.whitespace) {
+                        continue;
+                    }
+                    if printed_columns >= columns {
+                        return cells;
+                    }
+                    (" ".to_string(), 1)
+                } else if whitespace {
+                    // tabs are left in the line only when they are passed through,
+                    // in which case they advance to the next tab stop
+                    let mut text = String::new();
+                    let mut column = printed_columns;
+                    for c in group {
+                        let next = if c == '\t' {
+                            self.tab_stops.next(column)
+                        } else {
+                            column + c.width().unwrap_or(0)
+                        };
+                        if next > columns {
+                            truncated = true;
+                            break;
+                        }
+                        text.push(c);
+                        column = next;
+                    }
+                    (text, column - printed_columns)
+                } else {
+                    // like whitespace, only as much of the run as fits is collected
+                    let mut text = String::new();
+                    let mut width = 0;
+                    for c in group {
+                        width += c.width().unwrap_or(0);
+                        if printed_columns + width > columns {
+                            break;
+                        }
+                        text.push(c);
+                    }
+                    let width = text.width();
+                    (text, width)
+                };
+
+                printed_columns += width;
+                cells.push(Cell {
+                    color,
+                    width,
+                    whitespace,
+                    density: if whitespace { 0.0 } else { 1.0 },
+                    extent: if whitespace { 0.0 } else { 1.0 },
+                    text,
+                    glyph: None,
+                    background,
+                });
+
+                if truncated || printed_columns >= columns {
+                    return cells;
+                }
+            }
+        }
+
+        cells
+    }
+
+    /// Prints a line, which must end with a newline.
+    fn print_line<W: Write>(
+        &self,
+        writer: &mut W,
+        line: &str,
+        highlighter: &mut LineHighlighter,
+    ) -> Result<usize> {
+        if self.config.heatmap {
+            let cell = self.average_cell(&self.layout_line(line, highlighter, usize::MAX));
+            return self.print_cell(writer, cell);
+        }
+
+        let (left, right) = match self.config.edges {
+            Some(edges) => edges,
+            None => {
+                let cells = self.add_indent_guides(self.highlight_line_to_cells(line, highlighter));
+                return self.print_cells(writer, cells, self.guide_column());
+            }
+        };
+
+        let (left, right) = (left * self.config.scale, right * self.config.scale);
+        let mut cells = self.layout_line(line, highlighter, usize::MAX);
+        let width: usize = cells.iter().map(|cell| cell.width).sum();
+        if width <= left + right {
+            let cells = self.add_indent_guides(downscale(&cells, self.config.scale));
+            return self.print_cells(writer, cells, self.guide_column());
+        }
+
+        let mut right_cells = split_cells_off(&mut cells, width - right);
+        split_cells_off(&mut cells, left);
+
+        let cells = self.add_indent_guides(downscale(&cells, self.config.scale));
+        let mut printed_columns = self.print_cells(writer, cells, self.guide_column())?;
+        self.reset_color(writer)?;
+        self.set_attribute(writer, Attribute::Dim)?;
+        crossterm::queue!(writer, style::Print('…'))?;
+        self.set_attribute(writer, Attribute::Reset)?;
+        printed_columns += 1;
+
+        right_cells.retain(|cell| cell.width > 0);
+        printed_columns +=
+            self.print_cells(writer, downscale(&right_cells, self.config.scale), None)?;
+
+        Ok(printed_columns)
+    }
+
+    /// Prints the text of `cells` in their colors, padded or truncated to `width` columns,
+    /// followed by a rule separating it from the minimap.
+    fn print_source_text<W: Write>(
+        &self,
+        writer: &mut W,
+        cells: &[Cell],
+        width: usize,
+    ) -> Result<usize> {
+        let mut printed_columns = 0;
+        for cell in cells {
+            let remaining = width - printed_columns;
+            if remaining == 0 {
+                break;
+            }
+            if cell.whitespace {
+                self.reset_color(writer)?;
+                let blank = cell.width.min(remaining);
+                crossterm::queue!(writer, style::Print(" ".repeat(blank)))?;
+                printed_columns += blank;
+            } else {
+                let text = self.sanitize(&cell.text);
+                let text = truncate_to_width(&text, remaining);
+                self.set_foreground(writer, &cell.color)?;
+                crossterm::queue!(writer, style::Print(text))?;
+                printed_columns += text.width();
+            }
+        }
+
+        self.reset_color(writer)?;
+        crossterm::queue!(writer, style::Print(" ".repeat(width - printed_columns)))?;
+        self.set_attribute(writer, Attribute::Dim)?;
+        crossterm::queue!(writer, style::Print('│'))?;
+        self.set_attribute(writer, Attribute::Reset)?;
+        Ok(width + 1)
+    }
+
+    /// Prints a line with the order of its cells reversed, aligned to the right end of
+    /// `width` columns.
+    fn print_rtl_line<W: Write>(
+        &self,
+        writer: &mut W,
+        line: &str,
+        highlighter: &mut LineHighlighter,
+        width: usize,
+    ) -> Result<usize> {
+        let mut cells = self.add_indent_guides(self.highlight_line_to_cells(line, highlighter));
+        cells.reverse();
+        for cell in &mut cells {
+            cell.text = cell.text.chars().rev().collect();
+        }
+
+        let line_width: usize = cells.iter().map(|cell| cell.width).sum();
+        let padding = width.saturating_sub(line_width);
+        self.reset_color(writer)?;
+        crossterm::queue!(writer, style::Print(" ".repeat(padding)))?;
+        Ok(padding + self.print_cells(writer, cells, None)?)
+    }
+
+    /// Replaces the indentation of a line with blanks and guides at every indentation level.
+    fn add_indent_guides(&self, cells: Vec<Cell>) -> Vec<Cell> {
+        let indent: usize = cells
+            .iter()
+            .take_while(|cell| cell.whitespace)
+            .map(|cell| cell.width)
+            .sum();
+        let ink = cells.iter().any(|cell| !cell.whitespace);
+        if !self.config.indent_guides || indent == 0 || !ink {
+            return cells;
+        }
+
+        let blank = |width| Cell {
+            color: syntect::highlighting::Color::WHITE,
+            width,
+            whitespace: true,
+            density: 0.0,
+            extent: 0.0,
+            text: " ".repeat(width),
+            glyph: None,
+            background: None,
+        };
+        let guides = (0..)
+            .map(|level| level * self.tabs / self.config.scale)
+            .take_while(|column| *column < indent)
+            .dedup();
+
+        let mut guided = Vec::new();
+        let mut column = 0;
+        for guide in guides {
+            if guide > column {
+                guided.push(blank(guide - column));
+            }
+            guided.push(Cell {
+                glyph: Some(INDENT_GUIDE),
+                ..blank(1)
+            });
+            column = guide + 1;
+        }
+        if indent > column {
+            guided.push(blank(indent - column));
+        }
+        guided.extend(cells.into_iter().skip_while(|cell| cell.whitespace));
+        guided
+    }
+
+    /// Lays out a line over as many rows as needed to show it in full.
+    ///
+    /// Continuation rows start with blank cells as wide as the indentation of the line,
+    /// unless it takes more than half of the maximum number of columns or the next
+    /// character does not fit beside it. A character wider than a row gets a row of its own.
+    fn wrap_line(&self, line: &str, highlighter: &mut LineHighlighter) -> Vec<Vec<Cell>> {
+        let mut cells = downscale(
+            &self.layout_line(line, highlighter, usize::MAX),
+            self.config.scale,
+        );
+        let indent = cells
+            .first()
+            .filter(|cell| cell.whitespace)
+            .map_or(0, |cell| cell.width);
+        let indent = if indent.saturating_mul(2) > self.config.columns {
+            0
+        } else {
+            indent
+        };
+
+        let mut rows = Vec::new();
+        let mut continued = false;
+        loop {
+            // every row gets at least one character, even one wider than the row, and
+            // rows continuing a line leave out the indent if it does not fit beside it
+            let first_width = cells
+                .iter()
+                .find(|cell| cell.width > 0)
+                .and_then(|cell| cell.text.chars().find_map(|c| c.width().filter(|w| *w > 0)))
+                .unwrap_or(1);
+            let indent = if continued && indent + first_width <= self.config.columns {
+                indent
+            } else {
+                0
+            };
+            let rest = split_cells_off(&mut cells, (self.config.columns - indent).max(first_width));
+            if indent > 0 {
+                cells.insert(
+                    0,
+                    Cell {
+                        color: syntect::highlighting::Color::WHITE,
+                        width: indent,
+                        whitespace: true,
+                        density: 0.0,
+                        extent: 0.0,
+                        text: " ".repeat(indent),
+                        glyph: None,
+                        background: None,
+                    },
+                );
+            }
+            rows.push(self.add_indent_guides(cells));
+            if rest.iter().all(|cell| cell.width == 0) {
+                return rows;
+            }
+
+            cells = rest;
+            continued = true;
+        }
+    }
+
+    /// Merges `cells` into a single cell of the average color, whose density is
+    /// the fraction of columns that are not whitespace.
+    fn average_cell(&self, cells: &[Cell]) -> Cell {
+        let mut sums = [0.0; 3];
+        let mut ink = 0;
+        let mut total = 0;
+        for cell in cells {
+            total += cell.width;
+            if !cell.whitespace {
+                let weight = cell.width as f32;
+                sums[0] += cell.color.r as f32 * weight;
+                sums[1] += cell.color.g as f32 * weight;
+                sums[2] += cell.color.b as f32 * weight;
+                ink += cell.width;
+            }
+        }
+
+        if ink == 0 {
+            return Cell {
+                color: syntect::highlighting::Color::WHITE,
+                width: 1,
+                whitespace: true,
+                density: 0.0,
+                extent: 0.0,
+                text: " ".to_string(),
+                glyph: None,
+                background: None,
+            };
+        }
+
+        let average = |sum: f32| (sum / ink as f32).round() as u8;
+        Cell {
+            color: syntect::highlighting::Color {
+                r: average(sums[0]),
+                g: average(sums[1]),
+                b: average(sums[2]),
+                a: 0xff,
+            },
+            width: 1,
+            whitespace: false,
+            density: ink as f32 / total as f32,
+            extent: 1.0,
+            text: cells.iter().map(|cell| cell.text.as_str()).collect(),
+            glyph: None,
+            background: None,
+        }
+    }
+
+    /// Prints cells, marking the column `guide` if any.
+    fn print_cells<W: Write>(
+        &self,
+        writer: &mut W,
+        cells: Vec<Cell>,
+        guide: Option<usize>,
+    ) -> Result<usize> {
+        let mut printed_columns = 0;
+        for mut cell in cells {
+            if let Some(guide) = guide {
+                if printed_columns <= guide && guide < printed_columns + cell.width {
+                    let mut guide_cell = cell.split_off(guide - printed_columns);
+                    let rest = guide_cell.split_off(1);
+                    printed_columns += self.print_cell(writer, cell)?;
+                    printed_columns += self.print_guide_cell(writer, Some(guide_cell))?;
+                    cell = rest;
+                }
+            }
+            printed_columns += self.print_cell(writer, cell)?;
+        }
+
+        if let Some(guide) = guide {
+            if printed_columns <= guide {
+                self.reset_color(writer)?;
+                crossterm::queue!(writer, style::Print(" ".repeat(guide - printed_columns)))?;
+                printed_columns = guide + self.print_guide_cell(writer, None)?;
+            }
+        }
+
+        Ok(printed_columns)
+    }
+
+    fn print_cell<W: Write>(&self, writer: &mut W, mut cell: Cell) -> Result<usize> {
+        if self.row_blanked.get() && !cell.whitespace {
+            cell.whitespace = true;
+            cell.glyph = None;
+            cell.text = " ".repeat(cell.width);
+        }
+        let width = cell.width;
+        let background = cell
+            .background
+            .and_then(|color| convert_color(&color, self.config.color_mode));
+        let set_background = |writer: &mut W| -> Result<()> {
+            if let Some(background) = background {
+                crossterm::queue!(writer, style::SetBackgroundColor(background))?;
+            }
+            Ok(())
+        };
+        if let (true, Some(glyph)) = (cell.whitespace, cell.glyph) {
+            self.reset_color(writer)?;
+            self.set_whitespace_background(writer)?;
+            set_background(writer)?;
+            self.set_attribute(writer, Attribute::Dim)?;
+            crossterm::queue!(writer, style::Print(glyph.to_string().repeat(cell.width)))?;
+            self.set_attribute(writer, Attribute::Reset)?;
+        } else if cell.whitespace {
+            self.reset_color(writer)?;
+            self.set_whitespace_background(writer)?;
+            set_background(writer)?;
+            match self.config.ramp.first() {
+                Some(glyph) => {
+                    crossterm::queue!(writer, style::Print(glyph.to_string().repeat(cell.width)))?
+                }
+                // e.g. carriage returns and form feeds, which would move the cursor and
+                // are laid out as taking no columns
+                None if !self.config.raw_control_chars => {
+                    let text = cell.text.replace(|c: char| c.is_control() && c != '\t', "");
+                    crossterm::queue!(writer, style::Print(text))?
+                }
+                None => crossterm::queue!(writer, style::Print(cell.text))?,
+            }
+            if self.config.whitespace_background.is_some() || background.is_some() {
+                self.reset_color(writer)?;
+            }
+        } else {
+            if self.config.palette_summary.is_some() {
+                let color = (cell.color.r, cell.color.g, cell.color.b);
+                *self.color_usage.borrow_mut().entry(color).or_insert(0) += cell.width;
+            }
+            self.set_foreground(writer, &cell.color)?;
+            set_background(writer)?;
+            crossterm::queue!(
+                writer,
+                style::Print(
+                    cell.glyph
+                        .unwrap_or_else(|| self.glyph(&cell))
+                        .to_string()
+                        .repeat(cell.width)
+                )
+            )?;
+            if background.is_some() {
+                self.reset_color(writer)?;
+            }
+        }
+
+        Ok(width)
+    }
+
+    /// Prints a cell on the guide, where `None` stands for the space past the end of a line.
+    fn print_guide_cell<W: Write>(&self, writer: &mut W, cell: Option<Cell>) -> Result<usize> {
+        match cell {
+            Some(cell) if !cell.whitespace && !self.row_blanked.get() => {
+                let width = cell.width;
+                if let Some(background) = convert_color(&GUIDE_COLOR, self.config.color_mode) {
+                    crossterm::queue!(writer, style::SetBackgroundColor(background))?;
+                }
+                self.print_cell(writer, cell)?;
+                self.reset_color(writer)?;
+                Ok(width)
+            }
+            _ => {
+                self.reset_color(writer)?;
+                self.set_attribute(writer, Attribute::Dim)?;
+                crossterm::queue!(writer, style::Print('│'))?;
+                self.set_attribute(writer, Attribute::Reset)?;
+                Ok(1)
+            }
         }
     }
-}
 
-impl PrinterBuilder {
-    pub fn new() -> Self {
-        Default::default()
+    fn guide_column(&self) -> Option<usize> {
+        self.config
+            .guide
+            .map(|column| column / self.config.scale)
+            .filter(|column| *column < self.config.columns)
     }
 
-    pub fn build(&self) -> Printer {
-        Printer {
-            language: self.language.clone(),
-            columns: self.columns,
-            tabs: self.tabs,
-            true_color: self.true_color,
+    fn set_foreground<W: Write>(
+        &self,
+        writer: &mut W,
+        color: &syntect::highlighting::Color,
+    ) -> Result<()> {
+        let color = if self.row_dimmed.get() {
+            adjust_color(*color, DIMMED_BRIGHTNESS, 1.0)
+        } else {
+            *color
+        };
+        if let Some(color) = convert_color(&color, self.config.color_mode) {
+            crossterm::queue!(writer, style::SetForegroundColor(color))?;
         }
+        Ok(())
     }
 
-    pub fn language(&mut self, language: &str) -> &mut Self {
-        self.language = Some(language.to_string());
-        self
+    fn terminate_row<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match self.config.row_terminator {
+            RowTerminator::Lf => writer.write_all(b"\n")?,
+            RowTerminator::CrLf => writer.write_all(b"\r\n")?,
+            RowTerminator::None => {}
+        }
+        Ok(())
     }
 
-    pub fn columns(&mut self, columns: usize) -> &mut Self {
-        self.columns = columns;
-        self
+    fn reset_color<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if self.config.color_mode != ColorMode::NoColor {
+            crossterm::queue!(writer, style::ResetColor)?;
+            self.restore_row_background(writer)?;
+        }
+        Ok(())
     }
 
-    pub fn tabs(&mut self, tabs: usize) -> &mut Self {
-        self.tabs = tabs;
-        self
+    fn set_attribute<W: Write>(&self, writer: &mut W, attribute: Attribute) -> Result<()> {
+        if self.config.color_mode != ColorMode::NoColor {
+            crossterm::queue!(writer, style::SetAttribute(attribute))?;
+            if attribute == Attribute::Reset {
+                self.restore_row_background(writer)?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn true_color(&mut self, yes: bool) -> &mut Self {
-        self.true_color = yes;
-        self
+    fn set_whitespace_background<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if let Some(color) = self
+            .config
+            .whitespace_background
+            .and_then(|color| convert_color(&color, self.config.color_mode))
+        {
+            crossterm::queue!(writer, style::SetBackgroundColor(color))?;
+        }
+        Ok(())
     }
-}
-
-pub struct Printer {
-    language: Option<String>,
-    columns: usize,
-    tabs: usize,
-    true_color: bool,
-}
 
-impl Printer {
-    pub fn print_file<W, P>(&self, writer: &mut W, path: P) -> Result<()>
-    where
-        W: Write,
-        P: AsRef<Path>,
-    {
-        let file = File::open(&path)?;
-        let input_reader = InputReader::new(BufReader::new(file))?;
+    fn restore_row_background<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if let Some(background) = self.row_background.get() {
+            crossterm::queue!(writer, style::SetBackgroundColor(background))?;
+        }
+        Ok(())
+    }
 
-        let syntax = if let Some(lang) = &self.language {
-            SYNTAX_SET.find_syntax_by_token(lang)
-        } else {
-            SYNTAX_SET.find_syntax_for_file(path)?
+    fn glyph(&self, cell: &Cell) -> char {
+        if self.config.eighth_blocks {
+            let eighths = (cell.extent * 8.0).ceil() as usize;
+            return EIGHTH_BLOCKS[eighths.clamp(1, 8) - 1];
         }
-        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
 
-        let mut highlighter = HighlightLines::new(syntax, &THEME);
+        // the first glyph of the ramp stands for whitespace
+        match self.config.ramp.len() {
+            0 => CELL_GLYPH,
+            1 => self.config.ramp[0],
+            len => {
+                let index = (cell.density * (len - 1) as f32).ceil() as usize;
+                self.config.ramp[index.clamp(1, len - 1)]
+            }
+        }
+    }
 
-        self.print(writer, input_reader, &mut highlighter)
+    fn is_hidden(&self, region: &Region) -> bool {
+        let comment = || COMMENT.does_match(region.scopes.as_slice()).is_some();
+        match self.config.scope_filter {
+            Some(ScopeFilter::Comments) => !comment(),
+            Some(ScopeFilter::Code) => comment(),
+            None => false,
+        }
     }
 
-    pub fn print_from_reader<W, R>(&self, writer: &mut W, reader: &mut R) -> Result<()>
-    where
-        W: Write,
-        R: BufRead,
-    {
-        let input_reader = InputReader::new(reader)?;
+    fn is_deemphasized(&self, region: &Region) -> bool {
+        !self.config.emphasized_scopes.is_empty()
+            && self
+                .config
+                .emphasized_scopes
+                .iter()
+                .all(|selectors| selectors.does_match(region.scopes.as_slice()).is_none())
+    }
 
-        let syntax = if let Some(lang) = &self.language {
-            SYNTAX_SET.find_syntax_by_token(lang)
-        } else {
-            SYNTAX_SET.find_syntax_by_first_line(input_reader.first_line())
+    /// Replaces control characters other than tabs in text from inputs with their
+    /// symbols, unless `raw_control_chars` is set.
+    fn sanitize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let unsafe_char = |c: char| c.is_control() && c != '\t';
+        if self.config.raw_control_chars || !text.contains(unsafe_char) {
+            return Cow::Borrowed(text);
         }
-        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
-
-        let mut highlighter = HighlightLines::new(syntax, &THEME);
+        text.chars()
+            .map(|c| match c as u32 {
+                _ if !unsafe_char(c) => c,
+                // Control Pictures, e.g. ␛ for escape
+                code @ 0..=0x1f => char::from_u32(0x2400 + code).unwrap(),
+                0x7f => '\u{2421}',
+                _ => char::REPLACEMENT_CHARACTER,
+            })
+            .collect()
+    }
 
-        self.print(writer, input_reader, &mut highlighter)
+    fn scope_color(&self, region: &Region) -> syntect::highlighting::Color {
+        self.recolored(region.scopes.as_slice(), region.style.foreground)
     }
 
-    fn print<W, R>(
+    /// Returns the color set with `recolor` that best matches `scopes`, or `foreground`.
+    fn recolored(
         &self,
-        writer: &mut W,
-        mut input_reader: InputReader<R>,
-        mut highlighter: &mut HighlightLines,
-    ) -> Result<()>
-    where
-        W: Write,
-        R: BufRead,
-    {
-        let mut buf = String::new();
-        while input_reader.read_line(&mut buf)? {
-            let line = if self.tabs > 0 {
-                let expanded = expand_tabs(&buf, self.tabs);
-                buf.clear();
-                expanded
-            } else {
-                std::mem::take(&mut buf)
-            };
-
-            self.print_line(writer, &line, &mut highlighter)?;
+        scopes: &[Scope],
+        foreground: syntect::highlighting::Color,
+    ) -> syntect::highlighting::Color {
+        self.config
+            .recolor
+            .iter()
+            .filter_map(|(selectors, color)| {
+                selectors.does_match(scopes).map(|power| (power, color))
+            })
+            .max_by_key(|(power, _)| *power)
+            .map_or(foreground, |(_, color)| *color)
+    }
+}
 
-            crossterm::queue!(writer, style::ResetColor)?;
-            writeln!(writer)?;
-        }
+impl Cell {
+    /// Splits the cell so that it keeps at most `column` columns and returns the rest.
+    fn split_off(&mut self, column: usize) -> Cell {
+        let (left, right) = if self.whitespace {
+            // whitespace may contain tabs, so it is split into plain spaces
+            let left = column.min(self.width);
+            (" ".repeat(left), " ".repeat(self.width - left))
+        } else {
+            let left = truncate_to_width(&self.text, column);
+            (left.to_string(), self.text[left.len()..].to_string())
+        };
 
-        Ok(())
+        let right = Cell {
+            color: self.color,
+            width: self.width - left.width(),
+            whitespace: self.whitespace,
+            density: self.density,
+            extent: self.extent,
+            text: right,
+            glyph: self.glyph,
+            background: self.background,
+        };
+        self.width = left.width();
+        self.text = left;
+        right
     }
+}
 
-    fn print_line<W: Write>(
-        &self,
-        writer: &mut W,
-        line: &str,
-        highlighter: &mut HighlightLines,
-    ) -> Result<()> {
-        let regions = highlighter.highlight(&line, &SYNTAX_SET);
+/// Returns the color of each column of `cells`, where `None` is whitespace.
+fn column_colors(cells: &[Cell]) -> Vec<Option<syntect::highlighting::Color>> {
+    cells
+        .iter()
+        .flat_map(|cell| {
+            let color = Some(cell.color).filter(|_| !cell.whitespace);
+            std::iter::repeat_n(color, cell.width)
+        })
+        .collect()
+}
 
-        let mut printed_columns = 0;
-        for (style, region) in regions {
-            let color = convert_color(&style.foreground, self.true_color);
-
-            for (whitespace, group) in &region.chars().group_by(|c| c.is_whitespace()) {
-                let text: String = group.collect();
-                let width = text.width().min(self.columns - printed_columns);
-
-                if whitespace {
-                    let mut count = 0;
-                    let text: String = text
-                        .chars()
-                        .take_while(|c| {
-                            count += c.width().unwrap_or(0);
-                            count <= width
-                        })
-                        .collect();
-                    crossterm::queue!(writer, style::ResetColor, style::Print(text))?;
-                } else {
-                    crossterm::queue!(
-                        writer,
-                        style::SetForegroundColor(color),
-                        style::Print("▀".repeat(width))
-                    )?;
-                }
+/// Collapses every `scale` columns into a single cell.
+fn downscale(cells: &[Cell], scale: usize) -> Vec<Cell> {
+    if scale <= 1 {
+        return cells.to_vec();
+    }
 
-                if printed_columns + width >= self.columns {
-                    return Ok(());
-                } else {
-                    printed_columns += width;
+    // color, character starting at the column, and whether the column is not whitespace
+    let mut columns = Vec::new();
+    // background of each column
+    let mut backgrounds = Vec::new();
+    for cell in cells {
+        let start = columns.len();
+        if cell.whitespace {
+            columns.extend(std::iter::repeat_n((cell.color, None, false), cell.width));
+        } else {
+            for c in cell.text.chars() {
+                let width = c.width().unwrap_or(0);
+                if width > 0 {
+                    columns.push((cell.color, Some(c), true));
+                    columns.extend(std::iter::repeat_n((cell.color, None, true), width - 1));
                 }
             }
         }
+        backgrounds.extend(std::iter::repeat_n(cell.background, columns.len() - start));
+    }
 
-        Ok(())
+    columns
+        .chunks(scale)
+        .zip(backgrounds.chunks(scale))
+        .map(|(bucket, backgrounds)| {
+            let inked: Vec<_> = bucket.iter().filter(|(_, _, ink)| *ink).collect();
+            // the most common color wins
+            let color = inked
+                .iter()
+                .map(|(color, _, _)| *color)
+                .max_by_key(|color| inked.iter().filter(|(c, _, _)| c == color).count())
+                .unwrap_or(bucket[0].0);
+            let extent = bucket
+                .iter()
+                .rposition(|(_, _, ink)| *ink)
+                .map_or(0, |i| i + 1);
+            let text = if inked.is_empty() {
+                " ".to_string()
+            } else {
+                bucket.iter().filter_map(|(_, c, _)| *c).collect()
+            };
+            Cell {
+                color,
+                width: 1,
+                whitespace: inked.is_empty(),
+                density: inked.len() as f32 / scale as f32,
+                extent: extent as f32 / scale as f32,
+                text,
+                glyph: None,
+                background: backgrounds[0],
+            }
+        })
+        .collect()
+}
+
+/// Splits `cells` so that they keep at most `column` columns and returns the rest.
+fn split_cells_off(cells: &mut Vec<Cell>, column: usize) -> Vec<Cell> {
+    let mut printed_columns = 0;
+    for (i, cell) in cells.iter_mut().enumerate() {
+        if printed_columns + cell.width > column {
+            let right = cell.split_off(column - printed_columns);
+            let mut rest = cells.split_off(i + 1);
+            rest.insert(0, right);
+            return rest;
+        }
+        printed_columns += cell.width;
     }
+    Vec::new()
 }
 
 struct InputReader<R: BufRead> {
     inner: R,
-    first_line: String,
+    // None once the first line has been read or when the input is empty
+    first_line: Option<String>,
+    // final line read without its newline yet
+    #[cfg(feature = "follow")]
+    pending: String,
+    // lines read ahead by `buffer`
+    buffered: VecDeque<String>,
+    // number of lines left to read if limited with `limit`
+    remaining: Option<usize>,
 }
 
 impl<R: BufRead> InputReader<R> {
     fn new(mut reader: R) -> io::Result<Self> {
         let mut first_line = String::new();
-        reader.read_line(&mut first_line)?;
-        first_line = first_line.trim_end_matches('\n').to_string();
+        let bytes = reader.read_line(&mut first_line)?;
+        let first_line = if bytes > 0 {
+            Some(first_line.trim_end_matches('\n').to_string())
+        } else {
+            None
+        };
 
         let reader = InputReader {
             inner: reader,
             first_line,
+            #[cfg(feature = "follow")]
+            pending: String::new(),
+            buffered: VecDeque::new(),
+            remaining: None,
         };
         Ok(reader)
     }
 
     fn first_line(&self) -> &str {
-        &self.first_line
+        self.first_line.as_deref().unwrap_or_default()
+    }
+
+    /// Stops reading after `lines` more lines.
+    fn limit(&mut self, lines: usize) {
+        self.remaining = Some(lines);
+    }
+
+    /// Reads the input to the end, keeping only the last `lines` lines to be read.
+    fn skip_to_last(&mut self, lines: usize) -> io::Result<()> {
+        let mut buf = String::new();
+        let mut last = VecDeque::with_capacity(lines);
+        while self.read_line(&mut buf)? {
+            if lines == 0 {
+                buf.clear();
+                continue;
+            }
+            if last.len() == lines {
+                last.pop_front();
+            }
+            last.push_back(std::mem::take(&mut buf));
+        }
+        self.buffered = last;
+        Ok(())
+    }
+
+    /// Reads all the remaining lines ahead and returns them.
+    fn buffer(&mut self) -> io::Result<&VecDeque<String>> {
+        let mut buf = String::new();
+        let mut lines = VecDeque::new();
+        while self.read_line(&mut buf)? {
+            lines.push_back(std::mem::take(&mut buf));
+        }
+        self.buffered = lines;
+        Ok(&self.buffered)
     }
 
+    /// Reads a line without its newline. A carriage return before it is kept, so that
+    /// lines reach the highlighter with their original terminator once the newline is
+    /// put back.
     fn read_line(&mut self, buf: &mut String) -> io::Result<bool> {
-        if self.first_line.is_empty() {
+        // lines read ahead were counted against the limit when they were read
+        if let Some(line) = self.buffered.pop_front() {
+            buf.push_str(&line);
+            return Ok(true);
+        }
+        if self.remaining == Some(0) {
+            return Ok(false);
+        }
+        let read = if let Some(first_line) = self.first_line.take() {
+            buf.push_str(&first_line);
+            true
+        } else {
             let bytes = self.inner.read_line(buf)?;
             *buf = buf.trim_end_matches('\n').to_string();
-            Ok(bytes > 0)
-        } else {
-            buf.push_str(&self.first_line);
-            self.first_line.clear();
+            bytes > 0
+        };
+        if let (true, Some(remaining)) = (read, &mut self.remaining) {
+            *remaining -= 1;
+        }
+        Ok(read)
+    }
+
+    /// Like `read_line`, but holds back a final line without a newline until the rest
+    /// of it is written, returning false in the meantime.
+    #[cfg(feature = "follow")]
+    fn read_complete_line(&mut self, buf: &mut String) -> io::Result<bool> {
+        if let Some(first_line) = self.first_line.take() {
+            buf.push_str(&first_line);
+            return Ok(true);
+        }
+        if let Some(line) = self.buffered.pop_front() {
+            buf.push_str(&line);
+            return Ok(true);
+        }
+
+        self.inner.read_line(&mut self.pending)?;
+        if self.pending.ends_with('\n') {
+            buf.push_str(self.pending.trim_end_matches('\n'));
+            self.pending.clear();
             Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Loads the syntax definitions, which otherwise happens when they are first needed.
+pub fn load_syntaxes() {
+    lazy_static::initialize(&SYNTAX_SET);
+}
+
+/// Resolves the syntax of an input.
+///
+/// An explicit `language` (comma-separated names or extensions tried in order) takes
+/// precedence. Otherwise the file name or the extension of `path`, and then `first_line`
+/// (e.g. a shebang) are looked at. Falls back to plain text.
+pub fn detect_syntax(
+    language: Option<&str>,
+    path: Option<&Path>,
+    first_line: Option<&str>,
+) -> &'static SyntaxReference {
+    if let Some(language) = language {
+        find_syntax_by_tokens(language)
+    } else {
+        path.and_then(find_syntax_by_path)
+            .or_else(|| first_line.and_then(|line| SYNTAX_SET.find_syntax_by_first_line(line)))
+    }
+    .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text())
+}
+
+/// Returns the state of highlighting `prefix` in `syntax` after its last line, for
+/// highlighting what follows it with `PrinterBuilder::highlight_start`.
+pub fn highlight_start_after(syntax: &SyntaxReference, prefix: &str) -> HighlightStart {
+    let mut start = HighlightStart::new(syntax);
+    for line in prefix.split_inclusive('\n') {
+        start.advance(line, &SYNTAX_SET);
+    }
+    start
+}
+
+/// Reads and parses a Jupyter notebook at `path`.
+#[cfg(feature = "notebook")]
+fn read_notebook<R: BufRead>(reader: &mut R, path: &Path) -> Result<Vec<notebook::NotebookCell>> {
+    let mut data = Vec::new();
+    io::Read::read_to_end(reader, &mut data)?;
+    notebook::parse_notebook(&data)
+        .map_err(|err| anyhow!("failed to parse notebook {}: {}", path.display(), err))
+}
+
+/// Resolves the syntax of a cell of a notebook, which is plain text if unknown.
+#[cfg(feature = "notebook")]
+fn notebook_cell_syntax(cell: &notebook::NotebookCell) -> &'static SyntaxReference {
+    cell.language
+        .as_deref()
+        .and_then(find_syntax_by_tokens)
+        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text())
+}
+
+/// Counts the lines left to read.
+fn count_lines<R: BufRead>(mut input_reader: InputReader<R>) -> io::Result<usize> {
+    let mut lines = 0;
+    let mut buf = String::new();
+    while input_reader.read_line(&mut buf)? {
+        lines += 1;
+        buf.clear();
+    }
+    Ok(lines)
+}
+
+/// Tries each of the comma-separated tokens in order.
+fn find_syntax_by_tokens(tokens: &str) -> Option<&'static SyntaxReference> {
+    tokens
+        .split(',')
+        .map(str::trim)
+        .find_map(|token| SYNTAX_SET.find_syntax_by_token(token))
+}
+
+/// Detects the language from the file name or the extension without opening the file.
+fn find_syntax_by_path(path: &Path) -> Option<&'static SyntaxReference> {
+    find_syntax_by_file_name(path).or_else(|| find_syntax_by_extension(path))
+}
+
+/// Detects the language from the whole file name (e.g. Makefile).
+fn find_syntax_by_file_name(path: &Path) -> Option<&'static SyntaxReference> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    SYNTAX_SET.find_syntax_by_extension(file_name)
+}
+
+fn find_syntax_by_extension(path: &Path) -> Option<&'static SyntaxReference> {
+    let extension = path.extension().unwrap_or_default();
+    SYNTAX_SET.find_syntax_by_extension(extension.to_str().unwrap_or(""))
+}
+
+/// Number of columns taken by non-whitespace characters.
+fn ink_width(text: &str) -> usize {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.width().unwrap_or(0))
+        .sum()
+}
+
+/// Replaces everything but whitespace with spaces of the same width.
+fn blank_out(text: &str) -> String {
+    let mut blank = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_whitespace() {
+            blank.push(c);
+        } else {
+            blank.push_str(&" ".repeat(c.width().unwrap_or(0)));
+        }
+    }
+    blank
+}
+
+/// Columns tabs advance to.
+#[derive(Clone, Debug)]
+struct TabStops {
+    // ascending columns, past which stops are every interval columns
+    stops: Vec<usize>,
+    interval: usize,
+}
+
+impl TabStops {
+    /// Stops at the columns `stops` other than 0, or every `tabs` columns without any.
+    fn new(stops: &[usize], tabs: usize) -> Self {
+        let mut stops = stops.to_vec();
+        stops.retain(|stop| *stop > 0);
+        stops.sort_unstable();
+        stops.dedup();
+        // past the last stop, tabs advance by the last interval between stops
+        let interval = match stops[..] {
+            [] => tabs,
+            [stop] => stop,
+            [.., previous, last] => last - previous,
+        };
+        Self { stops, interval }
+    }
+
+    /// Returns the first stop after `column`.
+    fn next(&self, column: usize) -> usize {
+        match self.stops.iter().find(|stop| **stop > column) {
+            Some(stop) => *stop,
+            None => {
+                let last = self.stops.last().copied().unwrap_or(0);
+                column + self.interval - (column - last) % self.interval
+            }
         }
     }
 }
 
-fn expand_tabs(mut line: &str, tab_width: usize) -> String {
+fn expand_tabs(line: &str, tab_stops: &TabStops) -> String {
+    expand_tabs_from(line, tab_stops, &mut 0)
+}
+
+/// Like `expand_tabs`, but for text starting at column `cursor`, which gets advanced
+/// past the text.
+fn expand_tabs_from(mut line: &str, tab_stops: &TabStops, cursor: &mut usize) -> String {
     let mut buf = String::with_capacity(line.len() * 2);
-    let mut cursor = 0;
 
     while let Some(index) = line.find('\t') {
         if index > 0 {
-            cursor += index;
-            buf.push_str(&line[..index]);
+            let text = &line[..index];
+            *cursor += text.width();
+            buf.push_str(text);
         }
 
-        let spaces = tab_width - (cursor % tab_width);
-        cursor += spaces;
+        let spaces = tab_stops.next(*cursor) - *cursor;
+        *cursor += spaces;
         buf.push_str(&" ".repeat(spaces));
 
         line = &line[index + 1..];
     }
 
+    *cursor += line.width();
     buf.push_str(line);
 
     buf
 }
 
-fn convert_color(color: &syntect::highlighting::Color, true_color: bool) -> Color {
+fn truncate_to_width(text: &str, width: usize) -> &str {
+    let mut count = 0;
+    for (i, c) in text.char_indices() {
+        count += c.width().unwrap_or(0);
+        if count > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+fn adjust_color(
+    color: syntect::highlighting::Color,
+    brightness: f32,
+    contrast: f32,
+) -> syntect::highlighting::Color {
+    if brightness == 1.0 && contrast == 1.0 {
+        return color;
+    }
+
+    let adjust = |x: u8| {
+        let x = ((f32::from(x) - 127.5) * contrast + 127.5) * brightness;
+        x.round().clamp(0.0, 255.0) as u8
+    };
+    syntect::highlighting::Color {
+        r: adjust(color.r),
+        g: adjust(color.g),
+        b: adjust(color.b),
+        a: color.a,
+    }
+}
+
+fn lerp_color(
+    from: syntect::highlighting::Color,
+    to: syntect::highlighting::Color,
+    t: f32,
+) -> syntect::highlighting::Color {
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    syntect::highlighting::Color {
+        r: lerp(from.r, to.r),
+        g: lerp(from.g, to.g),
+        b: lerp(from.b, to.b),
+        a: 0xff,
+    }
+}
+
+fn convert_color(color: &syntect::highlighting::Color, color_mode: ColorMode) -> Option<Color> {
+    if color_mode == ColorMode::NoColor {
+        return None;
+    }
     if color.a == 0 {
-        Color::Reset
-    } else if true_color {
-        Color::Rgb {
+        return Some(Color::Reset);
+    }
+
+    let color = match color_mode {
+        ColorMode::TrueColor => Color::Rgb {
             r: color.r,
             g: color.g,
             b: color.b,
+        },
+        ColorMode::Ansi256 => {
+            let ansi_color = ansi_colours::ansi256_from_rgb((color.r, color.g, color.b));
+            Color::AnsiValue(ansi_color)
         }
-    } else {
-        let ansi_color = ansi_colours::ansi256_from_rgb((color.r, color.g, color.b));
-        Color::AnsiValue(ansi_color)
+        ColorMode::Ansi16 => ansi16_from_rgb(color.r, color.g, color.b),
+        ColorMode::NoColor => unreachable!(),
+    };
+    Some(color)
+}
+
+fn ansi16_from_rgb(r: u8, g: u8, b: u8) -> Color {
+    // xterm's default palette
+    const PALETTE: [(Color, (u8, u8, u8)); 16] = [
+        (Color::Black, (0, 0, 0)),
+        (Color::DarkRed, (205, 0, 0)),
+        (Color::DarkGreen, (0, 205, 0)),
+        (Color::DarkYellow, (205, 205, 0)),
+        (Color::DarkBlue, (0, 0, 238)),
+        (Color::DarkMagenta, (205, 0, 205)),
+        (Color::DarkCyan, (0, 205, 205)),
+        (Color::Grey, (229, 229, 229)),
+        (Color::DarkGrey, (127, 127, 127)),
+        (Color::Red, (255, 0, 0)),
+        (Color::Green, (0, 255, 0)),
+        (Color::Yellow, (255, 255, 0)),
+        (Color::Blue, (92, 92, 255)),
+        (Color::Magenta, (255, 0, 255)),
+        (Color::Cyan, (0, 255, 255)),
+        (Color::White, (255, 255, 255)),
+    ];
+
+    let distance = |(pr, pg, pb): (u8, u8, u8)| {
+        let dr = i32::from(r) - i32::from(pr);
+        let dg = i32::from(g) - i32::from(pg);
+        let db = i32::from(b) - i32::from(pb);
+        dr * dr + dg * dg + db * db
+    };
+
+    PALETTE
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb))
+        .map(|(color, _)| *color)
+        .unwrap()
+}
+
+/// Internals of the printer for the targets in the `fuzz` directory, which cargo-fuzz
+/// builds with `--cfg fuzzing`.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzzing {
+    use super::{InputReader, TabStops};
+    use std::io;
+
+    /// Expands the tabs of `line` to `stops` and every `tabs` columns past them.
+    pub fn expand_tabs(line: &str, stops: &[usize], tabs: usize) -> String {
+        super::expand_tabs(line, &TabStops::new(stops, tabs.max(1)))
+    }
+
+    /// Reads the lines of `data` as the printer does, without their newlines.
+    pub fn read_lines(data: &[u8]) -> io::Result<Vec<String>> {
+        let mut reader = InputReader::new(data)?;
+        let mut lines = Vec::new();
+        let mut buf = String::new();
+        while reader.read_line(&mut buf)? {
+            lines.push(std::mem::take(&mut buf));
+        }
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::PlainCapture;
+
+    /// Lays out `line` of a file named `name` as `builder` would print it.
+    fn layout(builder: &PrinterBuilder, name: &str, line: &str) -> Vec<Cell> {
+        let printer = builder.build();
+        let syntax = detect_syntax(None, Some(Path::new(name)), Some(line));
+        let mut highlighter = LineHighlighter::new(syntax, &printer.theme);
+        printer.highlight_line_to_cells(line, &mut highlighter)
+    }
+
+    /// Widths of `cells`, each paired with whether it is whitespace.
+    fn widths(cells: &[Cell]) -> Vec<(usize, bool)> {
+        cells
+            .iter()
+            .map(|cell| (cell.width, cell.whitespace))
+            .collect()
+    }
+
+    #[test]
+    fn leading_tabs_passed_through_advance_to_tab_stops() {
+        let mut builder = PrinterBuilder::new();
+        builder.tabs(4).expand_tabs(false);
+
+        let cells = layout(&builder, "a.txt", "\t\tx\n");
+        assert_eq!(widths(&cells), [(8, true), (1, false)]);
+        assert_eq!(cells[0].text, "\t\t");
+
+        let cells = layout(&builder, "a.txt", "  \tx\n");
+        assert_eq!(widths(&cells), [(4, true), (1, false)]);
+        assert_eq!(cells[0].text, "  \t");
+    }
+
+    /// Prints `text` of a file named `name` as `builder` would, without colors.
+    fn render(builder: &PrinterBuilder, name: &str, text: &str) -> (String, Dimensions) {
+        let mut capture = PlainCapture::new();
+        let dims = builder
+            .build()
+            .print_from_reader_with_path(&mut capture, &mut text.as_bytes(), Some(name))
+            .unwrap();
+        (capture.into_string(), dims)
+    }
+
+    /// Wraps `line` of a plain text file as `builder` would print it, with the text of
+    /// each row.
+    fn wrap(builder: &PrinterBuilder, line: &str) -> Vec<String> {
+        let printer = builder.build();
+        let syntax = detect_syntax(None, None, None);
+        let mut highlighter = LineHighlighter::new(syntax, &printer.theme);
+        printer
+            .wrap_line(line, &mut highlighter)
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn wrap_puts_wide_characters_wider_than_rows_on_rows_of_their_own() {
+        let mut builder = PrinterBuilder::new();
+        builder.wrap(true).columns(1);
+        assert_eq!(wrap(&builder, "漢字\n"), ["漢", "字"]);
+    }
+
+    #[test]
+    fn wrap_leaves_out_indent_not_fitting_beside_wide_characters() {
+        let mut builder = PrinterBuilder::new();
+        builder.wrap(true).columns(2);
+        assert_eq!(wrap(&builder, " 漢字\n"), [" ", "漢", "字"]);
+        builder.columns(3);
+        assert_eq!(wrap(&builder, " 漢字\n"), [" 漢", " 字"]);
+    }
+
+    #[test]
+    fn huge_runs_of_whitespace_are_laid_out_up_to_the_columns() {
+        let mut builder = PrinterBuilder::new();
+        builder.columns(10).color_mode(ColorMode::NoColor);
+        let line = format!("{}x\n", " ".repeat(100_000));
+        for name in ["a.txt", "a.rs"] {
+            let (output, dims) = render(&builder, name, &line);
+            assert_eq!(
+                dims,
+                Dimensions {
+                    rows: 1,
+                    columns: 10
+                }
+            );
+            assert_eq!(output, format!("{}\n", " ".repeat(10)));
+        }
+    }
+
+    #[test]
+    fn head_and_tail_keep_the_first_and_last_lines() {
+        let text = "a\nbb\nccc\n";
+        let rows = |limit: fn(&mut PrinterBuilder) -> &mut PrinterBuilder| {
+            let mut builder = PrinterBuilder::new();
+            builder.color_mode(ColorMode::NoColor);
+            limit(&mut builder);
+            let (output, dims) = render(&builder, "a.txt", text);
+            assert_eq!(dims.rows, output.lines().count());
+            output
+                .lines()
+                .map(|row| row.chars().count())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rows(|builder| builder.head(2)), [1, 2]);
+        assert_eq!(rows(|builder| builder.tail(2)), [2, 3]);
+        assert_eq!(rows(|builder| builder.head(10)), [1, 2, 3]);
+        assert_eq!(rows(|builder| builder.tail(10)), [1, 2, 3]);
+        assert!(rows(|builder| builder.head(0)).is_empty());
+        assert!(rows(|builder| builder.tail(0)).is_empty());
+    }
+
+    #[test]
+    fn annotation_labels_are_measured_as_printed() {
+        let mut builder = PrinterBuilder::new();
+        builder.annotate(1, syntect::highlighting::Color::WHITE, Some("a\x1bb"));
+        let (text, dims) = render(&builder, "a.txt", "x\n");
+        assert!(text.contains("a\u{241b}b"));
+        assert_eq!(dims.columns, text.lines().next().unwrap().width());
+    }
+
+    #[test]
+    fn carriage_returns_take_no_columns() {
+        let mut builder = PrinterBuilder::new();
+        builder
+            .color_mode(ColorMode::NoColor)
+            .pad_to(PadWidth::Columns(6));
+        for name in ["a.txt", "a.rs"] {
+            let (lf, _) = render(&builder, name, "abcd\n");
+            let (crlf, _) = render(&builder, name, "abcd\r\n");
+            assert_eq!(crlf, lf);
+            assert_eq!(crlf.trim_end_matches('\n').chars().count(), 6);
+        }
+    }
+
+    #[cfg(feature = "notebook")]
+    #[test]
+    fn notebooks_are_counted_by_cell() {
+        let source = InputSource::Static {
+            name: "a.ipynb",
+            text: r##"{
+                "metadata": {"language_info": {"name": "python"}},
+                "cells": [
+                    {"cell_type": "markdown", "source": ["# Title\n", "text"]},
+                    {"cell_type": "code", "source": "x = 1\ny = 2\n"},
+                    {"cell_type": "code", "source": ["z = 3"]}
+                ]
+            }"##,
+        };
+        let printer = PrinterBuilder::new().build();
+        let counts = printer.count_lines_by_language(&source).unwrap();
+        assert_eq!(counts, [("Markdown", 2), ("Python", 3)]);
+    }
+
+    #[test]
+    fn detect_syntax_prefers_language() {
+        let syntax = detect_syntax(Some("nope, py"), Some(Path::new("a.rs")), None);
+        assert_eq!(syntax.name, "Python");
+    }
+
+    #[test]
+    fn detect_syntax_from_extension() {
+        let syntax = detect_syntax(None, Some(Path::new("src/a.rs")), Some("#!/bin/sh"));
+        assert_eq!(syntax.name, "Rust");
+        let syntax = detect_syntax(None, Some(Path::new("Makefile")), None);
+        assert_eq!(syntax.name, "Makefile");
+    }
+
+    #[test]
+    fn detect_syntax_from_first_line() {
+        let syntax = detect_syntax(None, Some(Path::new("script")), Some("#!/bin/sh"));
+        assert_eq!(syntax.name, "Bourne Again Shell (bash)");
+        let syntax = detect_syntax(None, None, Some("#!/usr/bin/env python3"));
+        assert_eq!(syntax.name, "Python");
+    }
+
+    #[test]
+    fn detect_syntax_falls_back_to_plain_text() {
+        assert_eq!(detect_syntax(None, None, None).name, "Plain Text");
+        assert_eq!(detect_syntax(Some("nope"), None, None).name, "Plain Text");
+        let syntax = detect_syntax(None, Some(Path::new("a.unknown")), Some("text"));
+        assert_eq!(syntax.name, "Plain Text");
+    }
+
+    #[test]
+    fn line_comments_end_at_the_terminator() {
+        let printer = PrinterBuilder::new().build();
+        // unlike that of Rust, the line comment of C ends only at a newline
+        let syntax = detect_syntax(Some("c"), None, None);
+        let comment: ScopeSelectors = "comment".parse().unwrap();
+        for input in ["// c\nint x;\n", "// c\r\nint x;\r\n", "// c\nint x;"] {
+            // lines reach the highlighter as they do when printed
+            let mut reader = InputReader::new(input.as_bytes()).unwrap();
+            let mut highlighter = LineHighlighter::new(syntax, &printer.theme);
+            let mut commented = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() {
+                line.push('\n');
+                let regions = highlighter.highlight(&line, &SYNTAX_SET);
+                let code = regions.iter().find(|region| !region.text.trim().is_empty());
+                commented.push(
+                    comment
+                        .does_match(code.unwrap().scopes.as_slice())
+                        .is_some(),
+                );
+                line.clear();
+            }
+            assert_eq!(commented, [true, false], "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn css_in_style_block_is_highlighted() {
+        let printer = PrinterBuilder::new().build();
+        let syntax = detect_syntax(None, Some(Path::new("a.html")), None);
+        let mut highlighter = LineHighlighter::new(syntax, &printer.theme);
+        highlighter.highlight("<style>\n", &SYNTAX_SET);
+        let regions = highlighter.highlight("p { color: red; }\n", &SYNTAX_SET);
+
+        let property = regions
+            .iter()
+            .find(|region| region.text == "color")
+            .unwrap();
+        let css: ScopeSelectors = "source.css".parse().unwrap();
+        assert!(css.does_match(property.scopes.as_slice()).is_some());
+        assert_ne!(
+            Some(property.style.foreground),
+            printer.theme.settings.foreground
+        );
     }
 }
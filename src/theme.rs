@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use syntect::highlighting::{Color, Highlighter, Theme, ThemeSet};
+use syntect::parsing::Scope;
+
+pub const DEFAULT_THEME: &str = "Monokai Extended";
+
+// used when the bundled theme fails to load
+const FALLBACK_THEME: &str = "base16-ocean.dark";
+
+// scopes whose colors are reported in the JSON theme list
+const SAMPLE_SCOPES: &[&str] = &[
+    "comment",
+    "string",
+    "constant.numeric",
+    "keyword",
+    "storage.type",
+    "entity.name.function",
+];
+
+lazy_static! {
+    /// The bundled Monokai Extended, or why it failed to load
+    static ref BUNDLED_THEME: std::result::Result<Theme, String> = {
+        static DEFAULT_THEME_FILE: &[u8] =
+            include_bytes!("../themes/sublime-monokai-extended/Monokai Extended.tmTheme");
+
+        let mut reader = io::Cursor::new(DEFAULT_THEME_FILE);
+        ThemeSet::load_from_reader(&mut reader).map_err(|err| err.to_string())
+    };
+
+    /// Built-in themes: syntect's defaults plus the bundled Monokai Extended
+    static ref THEME_SET: ThemeSet = {
+        let mut theme_set = ThemeSet::load_defaults();
+        if let Ok(theme) = &*BUNDLED_THEME {
+            theme_set.themes.insert(DEFAULT_THEME.to_string(), theme.clone());
+        }
+        theme_set
+    };
+}
+
+/// Themes from the directory given to `load_theme_dir`, which take precedence over
+/// the built-in ones
+static USER_THEMES: OnceLock<BTreeMap<String, Theme>> = OnceLock::new();
+
+/// Makes every theme in `dir` available by its file name without the extension.
+///
+/// Themes that fail to load are skipped and returned as errors. This has no effect
+/// if called more than once.
+pub fn load_theme_dir(dir: &Path) -> Result<Vec<anyhow::Error>> {
+    let paths = ThemeSet::discover_theme_paths(dir)
+        .map_err(|err| anyhow!("failed to read theme directory {}: {}", dir.display(), err))?;
+
+    let mut themes = BTreeMap::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        match ThemeSet::get_theme(&path) {
+            Ok(theme) => {
+                themes.insert(name, theme);
+            }
+            Err(err) => errors.push(anyhow!(
+                "failed to parse theme file {}: {}",
+                path.display(),
+                err
+            )),
+        }
+    }
+
+    let _ = USER_THEMES.set(themes);
+    Ok(errors)
+}
+
+pub fn default_theme() -> &'static Theme {
+    find_theme(DEFAULT_THEME).unwrap_or_else(|| &THEME_SET.themes[FALLBACK_THEME])
+}
+
+pub fn find_theme(name: &str) -> Option<&'static Theme> {
+    USER_THEMES
+        .get()
+        .and_then(|themes| themes.get(name))
+        .or_else(|| THEME_SET.themes.get(name))
+}
+
+/// Built-in themes merged with the loaded ones, ordered by name.
+fn all_themes() -> BTreeMap<&'static str, &'static Theme> {
+    let mut themes: BTreeMap<_, _> = THEME_SET
+        .themes
+        .iter()
+        .map(|(name, theme)| (name.as_str(), theme))
+        .collect();
+    if let Some(user_themes) = USER_THEMES.get() {
+        themes.extend(
+            user_themes
+                .iter()
+                .map(|(name, theme)| (name.as_str(), theme)),
+        );
+    }
+    themes
+}
+
+/// Loads the theme at `name_or_path` if it is an existing file, or from standard input
+/// if it is "-", and looks up a built-in theme by name otherwise.
+///
+/// If the bundled default theme failed to load, it is replaced with another built-in
+/// theme unless `exact` is set, in which case the failure is reported instead.
+pub fn resolve_theme(name_or_path: &str, exact: bool) -> Result<Theme> {
+    if name_or_path == "-" {
+        // the parser needs to seek, which standard input cannot
+        let mut data = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut data)
+            .map_err(|err| anyhow!("failed to read theme from standard input: {}", err))?;
+        return ThemeSet::load_from_reader(&mut io::Cursor::new(data))
+            .map_err(|err| anyhow!("failed to parse theme from standard input: {}", err));
+    }
+    let path = Path::new(name_or_path);
+    if name_or_path == DEFAULT_THEME && find_theme(DEFAULT_THEME).is_none() {
+        return match &*BUNDLED_THEME {
+            Err(err) if exact => Err(anyhow!("failed to load the default theme: {}", err)),
+            _ => Ok(default_theme().clone()),
+        };
+    }
+    if path.is_file() {
+        ThemeSet::get_theme(path)
+            .map_err(|err| anyhow!("failed to parse theme file {}: {}", path.display(), err))
+    } else {
+        find_theme(name_or_path).cloned().ok_or_else(|| {
+            anyhow!(
+                "no such built-in theme: {} (see --list-themes)",
+                name_or_path
+            )
+        })
+    }
+}
+
+/// Names of the available themes in alphabetical order.
+pub fn theme_names() -> impl Iterator<Item = &'static str> {
+    all_themes().into_keys()
+}
+
+/// Describes the available themes for tools presenting a theme picker.
+pub fn theme_list_json() -> Value {
+    let themes = all_themes()
+        .into_iter()
+        .map(|(name, theme)| {
+            let highlighter = Highlighter::new(theme);
+            let scopes: Map<String, Value> = SAMPLE_SCOPES
+                .iter()
+                .map(|scope| {
+                    let scope = Scope::from_str(scope).unwrap();
+                    let style = highlighter.style_for_stack(&[scope]);
+                    (scope.build_string(), json!(to_hex(style.foreground)))
+                })
+                .collect();
+
+            json!({
+                "name": name,
+                "author": theme.author,
+                "background": theme.settings.background.map(to_hex),
+                "foreground": theme.settings.foreground.map(to_hex),
+                "scopes": scopes,
+            })
+        })
+        .collect();
+
+    Value::Array(themes)
+}
+
+/// Contrast ratio between two colors as defined by WCAG, from 1 for equal luminance
+/// to 21 for black and white.
+pub fn contrast_ratio(a: Color, b: Color) -> f32 {
+    let (a, b) = (relative_luminance(a), relative_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+fn relative_luminance(color: Color) -> f32 {
+    let linear = |component: u8| {
+        let c = component as f32 / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(color.r) + 0.7152 * linear(color.g) + 0.0722 * linear(color.b)
+}
+
+fn to_hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use std::io::{self, Write};
+
+/// Keeps the terminal in the alternate screen while alive.
+///
+/// The original screen is restored on drop, including when unwinding from a panic.
+pub struct AlternateScreen(());
+
+impl AlternateScreen {
+    pub fn enter() -> Result<Self> {
+        crossterm::execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(Self(()))
+    }
+
+    /// Blocks until the user presses q, Esc, or Ctrl-C.
+    pub fn wait_for_quit(&self) -> Result<()> {
+        io::stdout().flush()?;
+
+        terminal::enable_raw_mode()?;
+        let result = loop {
+            match event::read() {
+                Ok(Event::Key(KeyEvent { code, modifiers })) => match code {
+                    KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break Ok(()),
+                    _ => (),
+                },
+                Ok(_) => (),
+                Err(err) => break Err(err.into()),
+            }
+        };
+        terminal::disable_raw_mode()?;
+
+        result
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = crossterm::execute!(io::stdout(), LeaveAlternateScreen);
+    }
+}